@@ -0,0 +1,103 @@
+use carp2::cratesio::{self, get_crate_latest_versions};
+use carp2::utils::Result;
+use crates_index::Index;
+use std::env;
+use std::time::{Duration, Instant};
+
+const DEFAULT_CRATES: &[&str] = &[
+    "anyhow",
+    "bitflags",
+    "bytes",
+    "cc",
+    "cfg-if",
+    "chrono",
+    "clap",
+    "crossbeam",
+    "either",
+    "futures",
+    "getrandom",
+    "hashbrown",
+    "itoa",
+    "lazy_static",
+    "libc",
+    "log",
+    "memchr",
+    "num",
+    "once_cell",
+    "parking_lot",
+    "proc-macro2",
+    "quote",
+    "rand",
+    "rayon",
+    "regex",
+    "ryu",
+    "semver",
+    "serde",
+    "serde_json",
+    "smallvec",
+    "syn",
+    "thiserror",
+    "time",
+    "tokio",
+    "toml",
+    "url",
+];
+const RUNS: u32 = 5;
+
+/// The lookup as it used to be: one crate at a time, resolving the latest version twice.
+fn sequential(index: &Index, names: &[&str]) -> Result<()> {
+    for name in names {
+        let crte = index
+            .crate_(name)
+            .ok_or(format!("Crate '{}' not found.", name))?;
+        get_crate_latest_versions(&crte)?;
+        get_crate_latest_versions(&crte)?;
+    }
+    Ok(())
+}
+
+fn parallel(index: &Index, names: &[&str]) -> Result<()> {
+    for crte in cratesio::load_crates(index, names)? {
+        crte.latest_version()?;
+    }
+    Ok(())
+}
+
+fn time(
+    label: &str,
+    index: &Index,
+    names: &[&str],
+    lookup: fn(&Index, &[&str]) -> Result<()>,
+) -> Result<Duration> {
+    let mut total = Duration::ZERO;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        lookup(index, names)?;
+        total += start.elapsed();
+    }
+    let average = total / RUNS;
+    println!("{:<12} {:>10.2?} / run", label, average);
+    Ok(average)
+}
+
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with('-'))
+        .collect();
+    let names: Vec<&str> = if args.is_empty() {
+        DEFAULT_CRATES.to_vec()
+    } else {
+        args.iter().map(String::as_str).collect()
+    };
+
+    let index = cratesio::get_index()?;
+    println!("Looking up {} crates, {} runs each", names.len(), RUNS);
+    let before = time("sequential", &index, &names, sequential)?;
+    let after = time("parallel", &index, &names, parallel)?;
+    println!(
+        "speedup      {:>10.2}x",
+        before.as_secs_f64() / after.as_secs_f64()
+    );
+    Ok(())
+}
@@ -0,0 +1,351 @@
+use crate::cratesio::{load_crates, IndexedCrate};
+use crate::dependency::Dependency;
+use crate::lockfile::Lockfile;
+use crate::utils::Result;
+use crates_index::Index;
+use semver::{Version, VersionReq};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use toml::Value;
+
+/// A RustSec advisory, reduced to what's needed to tell whether a version is affected.
+#[derive(Debug, Clone)]
+pub struct Advisory {
+    pub id: String,
+    pub package: String,
+    pub title: String,
+    /// The kind of informational advisory, e.g. `unmaintained`, or `None` for vulnerabilities.
+    pub informational: Option<String>,
+    pub patched: Vec<VersionReq>,
+    pub unaffected: Vec<VersionReq>,
+}
+
+impl Advisory {
+    pub fn affects(&self, version: &Version) -> bool {
+        !self
+            .patched
+            .iter()
+            .chain(&self.unaffected)
+            .any(|version_req| version_req.matches(version))
+    }
+}
+
+fn parse_version_reqs(value: Option<&Value>, id: &str) -> Result<Vec<VersionReq>> {
+    value
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .map(|version_req| {
+            let version_req = version_req
+                .as_str()
+                .ok_or(format!("A version range in '{}' is not a string.", id))?;
+            Ok(version_req.parse()?)
+        })
+        .collect()
+}
+
+/// Parses an advisory, either a Markdown file with TOML front matter in a ```` ```toml ```` block
+/// and the title as its first heading, or an older TOML-only file.
+pub fn parse_advisory(contents: &str, markdown: bool) -> Result<Option<Advisory>> {
+    let (toml_str, heading) = if markdown {
+        let start = contents
+            .find("```toml")
+            .ok_or("Could not find the TOML front matter of an advisory.")?
+            + "```toml".len();
+        let end = start
+            + contents[start..]
+                .find("```")
+                .ok_or("The TOML front matter of an advisory is not closed.")?;
+        let heading = contents[end..]
+            .lines()
+            .find_map(|line| line.strip_prefix("# "))
+            .map(str::to_owned);
+        (&contents[start..end], heading)
+    } else {
+        (contents, None)
+    };
+
+    let value: Value = toml_str.parse()?;
+    let advisory = value
+        .get("advisory")
+        .ok_or("Could not locate the advisory value in an advisory.")?;
+    let id = advisory
+        .get("id")
+        .and_then(Value::as_str)
+        .ok_or("Could not read the id of an advisory.")?
+        .to_owned();
+    if advisory.get("withdrawn").is_some() {
+        return Ok(None);
+    }
+    let package = advisory
+        .get("package")
+        .and_then(Value::as_str)
+        .ok_or(format!(
+            "Could not read the package of the advisory '{}'.",
+            id
+        ))?
+        .to_owned();
+    let title = heading
+        .or_else(|| {
+            advisory
+                .get("title")
+                .and_then(Value::as_str)
+                .map(str::to_owned)
+        })
+        .unwrap_or_default();
+    let informational = advisory
+        .get("informational")
+        .and_then(Value::as_str)
+        .map(str::to_owned);
+
+    let (patched, unaffected) = match value.get("versions") {
+        Some(versions) => (
+            parse_version_reqs(versions.get("patched"), &id)?,
+            parse_version_reqs(versions.get("unaffected"), &id)?,
+        ),
+        None => (
+            parse_version_reqs(advisory.get("patched_versions"), &id)?,
+            parse_version_reqs(advisory.get("unaffected_versions"), &id)?,
+        ),
+    };
+
+    Ok(Some(Advisory {
+        id,
+        package,
+        title,
+        informational,
+        patched,
+        unaffected,
+    }))
+}
+
+/// Loads every advisory for crates.io crates from a checkout of the RustSec advisory-db, skipping
+/// withdrawn ones. An advisory file that can't be read is skipped with a warning naming it, so one
+/// bad file doesn't stop the audit.
+pub fn load_advisories(db_path: &Path) -> Result<Vec<Advisory>> {
+    let crates_dir = db_path.join("crates");
+    if !crates_dir.is_dir() {
+        return Err(format!(
+            "Could not find an advisory database at '{}'.",
+            db_path.display()
+        )
+        .into());
+    }
+
+    let mut advisories = vec![];
+    for crate_dir in fs::read_dir(crates_dir)? {
+        let crate_dir = crate_dir?.path();
+        if !crate_dir.is_dir() {
+            continue;
+        }
+        for file in fs::read_dir(crate_dir)? {
+            let file = file?.path();
+            let markdown = match file.extension().and_then(|extension| extension.to_str()) {
+                Some("md") => true,
+                Some("toml") => false,
+                _ => continue,
+            };
+            match fs::read_to_string(&file)
+                .map_err(|err| err.into())
+                .and_then(|contents| parse_advisory(&contents, markdown))
+            {
+                Ok(parsed) => advisories.extend(parsed),
+                Err(err) => eprintln!(
+                    "WARNING skipping the advisory '{}': {}",
+                    file.display(),
+                    err
+                ),
+            }
+        }
+    }
+    advisories.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(advisories)
+}
+
+/// What an advisory was found against.
+#[derive(Debug, Clone)]
+pub enum AuditTarget {
+    Locked(Version),
+    /// A manifest requirement whose lowest allowed version is affected.
+    Required(VersionReq),
+}
+
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub advisory: Advisory,
+    pub name: String,
+    pub target: AuditTarget,
+    /// The lowest non-yanked, stable version from the index above the affected one that the
+    /// advisory doesn't affect.
+    pub upgrade: Option<Version>,
+}
+
+fn minimal_safe_upgrade(
+    advisory: &Advisory,
+    crte: &IndexedCrate,
+    from: &Version,
+) -> Option<Version> {
+    crte.versions
+        .iter()
+        .zip(crte.crte.versions())
+        .filter(|(version, crate_version)| {
+            *version > from
+                && version.pre.is_empty()
+                && !crate_version.is_yanked()
+                && !advisory.affects(version)
+        })
+        .map(|(version, _)| version)
+        .min()
+        .cloned()
+}
+
+/// Checks the locked crates.io packages and the manifest's crates.io requirements against the
+/// advisories. Git, path and alternate-registry packages aren't covered by the database.
+pub fn audit(
+    index: &Index,
+    advisories: &[Advisory],
+    lockfile: Option<&Lockfile>,
+    dependencies: &[Dependency],
+) -> Result<Vec<Finding>> {
+    let mut targets: Vec<(String, AuditTarget)> = vec![];
+    if let Some(lockfile) = lockfile {
+        for package in &lockfile.packages {
+            if package.is_from_crates_io() {
+                targets.push((
+                    package.name.clone(),
+                    AuditTarget::Locked(package.version.clone()),
+                ));
+            }
+        }
+    }
+    for dependency in dependencies {
+        if dependency.git().is_some()
+            || dependency.path().is_some()
+            || dependency.registry().is_some()
+        {
+            continue;
+        }
+        targets.push((
+            dependency.crate_name().to_owned(),
+            AuditTarget::Required(dependency.version_req.clone()),
+        ));
+    }
+    targets.retain(|(name, _)| advisories.iter().any(|advisory| &advisory.package == name));
+
+    let names: BTreeSet<&str> = targets.iter().map(|(name, _)| name.as_str()).collect();
+    let names: Vec<&str> = names.into_iter().collect();
+    let crates = load_crates(index, &names)?;
+
+    let mut findings = vec![];
+    for (name, target) in &targets {
+        let crte = names
+            .iter()
+            .position(|loaded| *loaded == name)
+            .map(|position| &crates[position])
+            .ok_or(format!("Could not find the crate '{}' in the index.", name))?;
+        let version = match target {
+            AuditTarget::Locked(version) => Some(version.clone()),
+            AuditTarget::Required(version_req) => crte
+                .versions
+                .iter()
+                .filter(|version| version_req.matches(version))
+                .min()
+                .cloned(),
+        };
+        let version = match version {
+            Some(version) => version,
+            None => continue,
+        };
+        for advisory in advisories {
+            if &advisory.package == name && advisory.affects(&version) {
+                findings.push(Finding {
+                    advisory: advisory.clone(),
+                    name: name.clone(),
+                    target: target.clone(),
+                    upgrade: minimal_safe_upgrade(advisory, crte, &version),
+                });
+            }
+        }
+    }
+    Ok(findings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    const MARKDOWN: &str = r#"```toml
+[advisory]
+id = "RUSTSEC-2021-0001"
+package = "demo"
+date = "2021-01-01"
+
+[versions]
+patched = [">= 1.2.3"]
+unaffected = ["< 1.0.0"]
+```
+
+# Demo does something unsound
+
+More text.
+"#;
+
+    #[test]
+    fn parses_markdown_advisories() {
+        let advisory = parse_advisory(MARKDOWN, true).unwrap().unwrap();
+        assert_eq!(advisory.id, "RUSTSEC-2021-0001");
+        assert_eq!(advisory.package, "demo");
+        assert_eq!(advisory.title, "Demo does something unsound");
+        assert_eq!(advisory.informational, None);
+        assert!(advisory.affects(&Version::new(1, 2, 2)));
+        assert!(!advisory.affects(&Version::new(1, 2, 3)));
+        assert!(!advisory.affects(&Version::new(0, 9, 0)));
+    }
+
+    #[test]
+    fn parses_toml_advisories() {
+        let contents = r#"
+[advisory]
+id = "RUSTSEC-2018-0001"
+package = "old"
+title = "Old style"
+informational = "unmaintained"
+patched_versions = ["^0.5"]
+"#;
+        let advisory = parse_advisory(contents, false).unwrap().unwrap();
+        assert_eq!(advisory.title, "Old style");
+        assert_eq!(advisory.informational.as_deref(), Some("unmaintained"));
+        assert!(advisory.affects(&Version::new(0, 4, 0)));
+        assert!(!advisory.affects(&Version::new(0, 5, 1)));
+    }
+
+    #[test]
+    fn skips_withdrawn_advisories() {
+        let contents = MARKDOWN.replace("date = ", "withdrawn = \"2021-02-01\"\ndate = ");
+        assert!(parse_advisory(&contents, true).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_unclosed_front_matter() {
+        assert!(parse_advisory("```toml\n[advisory]\n", true).is_err());
+    }
+
+    #[test]
+    fn bad_advisory_files_are_skipped() {
+        let db = scratch_dir("advisory-db");
+        let crate_dir = db.join("crates").join("demo");
+        fs::create_dir_all(&crate_dir).unwrap();
+        fs::write(crate_dir.join("RUSTSEC-2021-0001.md"), MARKDOWN).unwrap();
+        fs::write(
+            crate_dir.join("RUSTSEC-2021-0002.md"),
+            "```toml\n[advisory]\n",
+        )
+        .unwrap();
+        fs::write(crate_dir.join("README.txt"), "not an advisory").unwrap();
+        let advisories = load_advisories(&db).unwrap();
+        assert_eq!(advisories.len(), 1);
+        assert_eq!(advisories[0].id, "RUSTSEC-2021-0001");
+    }
+}
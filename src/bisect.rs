@@ -0,0 +1,138 @@
+use crate::dependency::Dependency;
+use crate::utils::Result;
+
+/// A dependency whose requirement differs between two manifests.
+#[derive(Debug, Clone)]
+pub struct Bump {
+    pub before: Dependency,
+    pub after: Dependency,
+}
+
+/// The dependencies in both manifests whose requirement changed, in the order of `after`.
+pub fn changed_dependencies(before: &[Dependency], after: &[Dependency]) -> Vec<Bump> {
+    after
+        .iter()
+        .filter_map(|new| {
+            let old = before.iter().find(|old| old.name == new.name)?;
+            (old.version_req != new.version_req).then(|| Bump {
+                before: old.clone(),
+                after: new.clone(),
+            })
+        })
+        .collect()
+}
+
+/// The dependencies of `after` with only the bumps at `applied` kept, and every other bump
+/// reverted.
+pub fn dependencies_with(
+    after: &[Dependency],
+    bumps: &[Bump],
+    applied: &[usize],
+) -> Vec<Dependency> {
+    after
+        .iter()
+        .map(|dependency| {
+            match bumps
+                .iter()
+                .enumerate()
+                .find(|(_, bump)| bump.after.name == dependency.name)
+            {
+                Some((position, bump)) if !applied.contains(&position) => bump.before.clone(),
+                _ => dependency.clone(),
+            }
+        })
+        .collect()
+}
+
+/// Narrows down which of `count` changes make `fails` fail, given that all of them together do
+/// and none of them don't, using delta debugging. The result is minimal: dropping any one change
+/// from it makes the failure go away.
+pub fn minimal_failing_set(
+    count: usize,
+    mut fails: impl FnMut(&[usize]) -> Result<bool>,
+) -> Result<Vec<usize>> {
+    let mut changes: Vec<usize> = (0..count).collect();
+    let mut granularity = 2;
+    while changes.len() >= 2 {
+        let chunk_len = changes.len().div_ceil(granularity);
+        let chunks: Vec<Vec<usize>> = changes.chunks(chunk_len).map(<[usize]>::to_vec).collect();
+
+        let mut narrowed = false;
+        for chunk in &chunks {
+            if fails(chunk)? {
+                changes = chunk.clone();
+                granularity = 2;
+                narrowed = true;
+                break;
+            }
+        }
+        if !narrowed && chunks.len() > 2 {
+            for chunk in &chunks {
+                let complement: Vec<usize> = changes
+                    .iter()
+                    .copied()
+                    .filter(|change| !chunk.contains(change))
+                    .collect();
+                if fails(&complement)? {
+                    changes = complement;
+                    granularity = (granularity - 1).max(2);
+                    narrowed = true;
+                    break;
+                }
+            }
+        }
+        if !narrowed {
+            if granularity >= changes.len() {
+                break;
+            }
+            granularity = (granularity * 2).min(changes.len());
+        }
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs the bisection with a failure caused by having every change in `culprits`, counting
+    /// the trials.
+    fn bisect(count: usize, culprits: &[usize]) -> (Vec<usize>, usize) {
+        let mut trials = 0;
+        let found = minimal_failing_set(count, |applied| {
+            trials += 1;
+            Ok(culprits.iter().all(|culprit| applied.contains(culprit)))
+        })
+        .unwrap();
+        (found, trials)
+    }
+
+    #[test]
+    fn finds_a_single_culprit() {
+        for culprit in 0..7 {
+            assert_eq!(bisect(7, &[culprit]).0, vec![culprit]);
+        }
+    }
+
+    #[test]
+    fn finds_changes_that_only_fail_together() {
+        assert_eq!(bisect(8, &[1, 6]).0, vec![1, 6]);
+        assert_eq!(bisect(5, &[0, 2, 4]).0, vec![0, 2, 4]);
+    }
+
+    #[test]
+    fn keeps_a_lone_change() {
+        assert_eq!(bisect(1, &[0]), (vec![0], 0));
+    }
+
+    #[test]
+    fn stops_at_the_first_error() {
+        let mut trials = 0;
+        let result = minimal_failing_set(4, |_| {
+            trials += 1;
+            Err("the verify command was killed".into())
+        });
+        assert!(result.is_err());
+        assert_eq!(trials, 1);
+    }
+}
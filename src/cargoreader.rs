@@ -1,10 +1,17 @@
-use crate::common::get_cargo_path;
+use crate::common::{get_cargo_path, lock_manifest};
 use crate::dependency::{get_versions_from_str, Dependency, RawToml};
-use crate::utils::Result;
+use crate::format::inline_value;
+use crate::history::{self, HistoryEntry};
+use crate::utils::{write_file_atomically, Result};
+use std::collections::HashMap;
 use std::fs;
 use toml::Value;
+use toml_edit::{Document, Item, Table, TableLike};
 
-pub fn parse_dependency_value<'a>(name: &str, value: Value) -> Result<Dependency> {
+/// A package's `[features]` table: each feature and what it enables.
+pub type PackageFeatures = HashMap<String, Vec<String>>;
+
+pub fn parse_dependency_value(name: &str, value: Value) -> Result<Dependency> {
     if let Some(string) = value.as_str() {
         let (version_req, version) = get_versions_from_str(string)?;
         Ok(Dependency {
@@ -66,22 +73,347 @@ pub fn parse_cargo_file(file_value: Value) -> Result<Vec<Dependency>> {
     Ok(dependencies)
 }
 
-pub fn write_dependencies(dependencies: Vec<Dependency>) -> Result<()> {
-    let mut cargo_file = read_cargo_file()?;
-    let mut new_cargo_deps = Value::Table(toml::map::Map::new());
-    let cargo_deps_table = new_cargo_deps.as_table_mut().unwrap();
+/// Parses a new manifest back and checks it has exactly the given dependencies.
+fn check_dependencies(new_cargo_file: &str, dependencies: &[Dependency]) -> Result<()> {
+    let mut expected: Vec<(String, String)> = dependencies
+        .iter()
+        .map(|dependency| {
+            (
+                dependency.name.to_owned(),
+                dependency.version_req.to_string(),
+            )
+        })
+        .collect();
+    let mut written: Vec<(String, String)> = parse_cargo_file(new_cargo_file.parse()?)?
+        .into_iter()
+        .map(|dependency| (dependency.name, dependency.version_req.to_string()))
+        .collect();
+    expected.sort();
+    written.sort();
+    if written != expected {
+        return Err(
+            "The new manifest does not have the expected dependencies, so it was not written."
+                .into(),
+        );
+    }
+    Ok(())
+}
+
+/// A value as toml_edit keeps it, laid out the way carp writes values.
+fn edit_value(value: &Value) -> Result<toml_edit::Value> {
+    Ok(inline_value(value).parse()?)
+}
+
+/// Replaces an item's value, keeping the whitespace and comments around it.
+fn replace_item(item: &mut Item, value: &Value) -> Result<()> {
+    let mut new_value = edit_value(value)?;
+    if let Some(old_value) = item.as_value() {
+        *new_value.decor_mut() = old_value.decor().clone();
+    }
+    *item = Item::Value(new_value);
+    Ok(())
+}
+
+/// Changes a dependency's entry from its old value to a new one. When both are tables, only the
+/// keys that changed are rewritten, so an expanded `[dependencies.name]` table stays expanded.
+fn update_entry(item: &mut Item, old: &Value, new: &Value) -> Result<()> {
+    let (old_table, new_table) = match (old.as_table(), new.as_table()) {
+        (Some(old_table), Some(new_table)) if item.is_table_like() => (old_table, new_table),
+        _ => return replace_item(item, new),
+    };
+    let table = item.as_table_like_mut().unwrap();
+    for key in old_table.keys() {
+        if !new_table.contains_key(key) {
+            table.remove(key);
+        }
+    }
+    for (key, value) in new_table {
+        match table.get_mut(key) {
+            Some(_) if old_table.get(key) == Some(value) => (),
+            Some(existing) => replace_item(existing, value)?,
+            None => {
+                table.insert(key, Item::Value(edit_value(value)?));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Adds a `name = value` entry to a dependency table. If the table's entries are sorted, the new
+/// one goes where it keeps them sorted, and otherwise after the last of them. Comments stay with
+/// the entries they're written above.
+fn insert_entry(table: &mut dyn TableLike, name: &str, value: toml_edit::Value) {
+    let names: Vec<&str> = table
+        .iter()
+        .filter(|(_, item)| item.is_value() || item.as_table().is_some_and(Table::is_dotted))
+        .map(|(name, _)| name)
+        .collect();
+    let sorted = names.windows(2).all(|pair| pair[0] <= pair[1]);
+    table.insert(name, Item::Value(value));
+    if sorted {
+        table.sort_values();
+    }
+}
+
+/// A manifest's text with the entries of its `[dependencies]` table changed to the given
+/// dependencies. Only the entries that differ are touched, so the rest of the manifest, comments
+/// included, is kept as it was written.
+fn edit_dependency_entries(cargo_text: &str, dependencies: &[Dependency]) -> Result<String> {
+    let old_manifest: Value = cargo_text.parse()?;
+    let empty = toml::value::Table::new();
+    let old_dependencies = old_manifest
+        .get("dependencies")
+        .and_then(Value::as_table)
+        .unwrap_or(&empty);
+    let mut document: Document = cargo_text.parse()?;
+    let table = document
+        .entry("dependencies")
+        .or_insert(toml_edit::table())
+        .as_table_like_mut()
+        .ok_or("Could not parse the dependencies value to a table in the Cargo.toml file given.")?;
 
+    for name in old_dependencies.keys() {
+        if !dependencies
+            .iter()
+            .any(|dependency| &dependency.name == name)
+        {
+            table.remove(name);
+        }
+    }
     for dependency in dependencies {
-        cargo_deps_table.insert(
-            dependency.name,
-            match dependency.raw_toml_value {
-                RawToml::String(string) => string,
-                RawToml::Table(table) => table,
-            },
+        let value = match &dependency.raw_toml_value {
+            RawToml::String(value) | RawToml::Table(value) => value,
+        };
+        match (
+            table.get_mut(&dependency.name),
+            old_dependencies.get(&dependency.name),
+        ) {
+            (Some(_), Some(old)) if old == value => (),
+            (Some(item), Some(old)) => update_entry(item, old, value)?,
+            _ => insert_entry(table, &dependency.name, edit_value(value)?),
+        }
+    }
+    Ok(document.to_string())
+}
+
+/// The manifest with its dependencies replaced, parsed back and checked to have exactly the given
+/// dependencies. Everything but the changed entries is kept as it was written.
+pub fn manifest_with_dependencies(dependencies: Vec<Dependency>) -> Result<String> {
+    let cargo_text = fs::read_to_string(get_cargo_path())?;
+    let new_cargo_file = edit_dependency_entries(&cargo_text, &dependencies)?;
+    check_dependencies(&new_cargo_file, &dependencies)?;
+    Ok(new_cargo_file)
+}
+
+/// Writes a new manifest while the caller holds the manifest lock, saving the previous state for
+/// `carp undo` under `reason`.
+fn write_locked_manifest(new_cargo_file: &str, reason: &str) -> Result<HistoryEntry> {
+    let entry = history::record(reason)?;
+    write_file_atomically(&get_cargo_path(), new_cargo_file)?;
+    Ok(entry)
+}
+
+/// Edits the manifest's dependencies, saving its previous state for `carp undo` under `reason`.
+/// The manifest is locked before it's read for `edit`, and the new manifest is checked before it
+/// replaces the old one. Returns the history entry with the manifest from before the edit.
+pub fn edit_dependencies(
+    reason: &str,
+    edit: impl FnOnce(Vec<Dependency>) -> Result<Vec<Dependency>>,
+) -> Result<HistoryEntry> {
+    let _lock = lock_manifest()?;
+    let dependencies = edit(parse_cargo_file(read_cargo_file()?)?)?;
+    let new_cargo_file = manifest_with_dependencies(dependencies)?;
+    write_locked_manifest(&new_cargo_file, reason)
+}
+
+/// Replaces the manifest with a new version of what it was, under the manifest lock. If the
+/// manifest changed since `current` was read from it, nothing is written.
+pub fn replace_manifest(current: &str, new_cargo_file: &str, reason: &str) -> Result<()> {
+    let _lock = lock_manifest()?;
+    if fs::read_to_string(get_cargo_path())? != current {
+        return Err(
+            "The manifest changed while carp was working on it, so it was not written.".into(),
         );
     }
+    write_locked_manifest(new_cargo_file, reason)?;
+    Ok(())
+}
+
+/// Adds a dependency, or replaces the one with its name, keeping the rest of the manifest as it
+/// was written.
+pub fn add_dependency(dependency: Dependency, reason: &str) -> Result<()> {
+    let _lock = lock_manifest()?;
+    let mut dependencies = parse_cargo_file(read_cargo_file()?)?;
+    dependencies.retain(|existing| existing.name != dependency.name);
+    dependencies.push(dependency);
+    let new_cargo_file = manifest_with_dependencies(dependencies)?;
+    write_locked_manifest(&new_cargo_file, reason)?;
+    Ok(())
+}
 
-    cargo_file["dependencies"] = new_cargo_deps;
-    let new_cargo_file = toml::ser::to_string(&cargo_file)?;
-    Ok(fs::write(get_cargo_path(), new_cargo_file)?)
+pub fn parse_package_features(file_value: &Value) -> Result<PackageFeatures> {
+    let mut features = HashMap::new();
+    if let Some(features_value) = file_value.get("features") {
+        let features_table = features_value
+            .as_table()
+            .ok_or("Could not parse the features value to a table in the Cargo.toml file given.")?;
+        for (name, enables) in features_table {
+            let enables = enables
+                .as_array()
+                .ok_or(format!("The feature '{}' is not an array.", name))?
+                .iter()
+                .map(|enabled| {
+                    enabled.as_str().map(str::to_owned).ok_or(format!(
+                        "The feature '{}' enables something that is not a string.",
+                        name
+                    ))
+                })
+                .collect::<std::result::Result<_, _>>()?;
+            features.insert(name.to_owned(), enables);
+        }
+    }
+    Ok(features)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dependency::transform_dependency_version;
+
+    const MANIFEST: &str = r#"[package]
+name = "demo"
+
+[dependencies]
+# Pinned until the 2.0 migration.
+rand = "0.7" # carp:allow(exact-pin)
+serde = { version = "1.0", features = ["derive"] }
+
+[dependencies.tokio]
+# Runtime for the server.
+version = "1.20"
+features = ["full"]
+"#;
+
+    /// The manifest's dependencies with one of them moved to a new requirement.
+    fn with_version(name: &str, version: &str) -> Vec<Dependency> {
+        parse_cargo_file(MANIFEST.parse().unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|dependency| {
+                if dependency.name == name {
+                    transform_dependency_version(version, dependency).unwrap()
+                } else {
+                    dependency
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn updates_keep_comments_and_layout() {
+        let edited = edit_dependency_entries(MANIFEST, &with_version("rand", "0.8")).unwrap();
+        assert_eq!(edited, MANIFEST.replace("rand = \"0.7\"", "rand = \"0.8\""));
+        let edited = edit_dependency_entries(MANIFEST, &with_version("tokio", "1.38")).unwrap();
+        assert_eq!(
+            edited,
+            MANIFEST.replace("version = \"1.20\"", "version = \"1.38\"")
+        );
+        let edited = edit_dependency_entries(MANIFEST, &with_version("serde", "1.0.200")).unwrap();
+        assert_eq!(
+            edited,
+            MANIFEST.replace("version = \"1.0\"", "version = \"1.0.200\"")
+        );
+    }
+
+    /// A manifest's text with a dependency added to it.
+    fn add(cargo_text: &str, name: &str, requirement: &str) -> String {
+        let mut dependencies = match cargo_text.parse::<Value>().unwrap().get("dependencies") {
+            Some(_) => parse_cargo_file(cargo_text.parse().unwrap()).unwrap(),
+            None => vec![],
+        };
+        dependencies.push(parse_dependency_value(name, Value::from(requirement)).unwrap());
+        edit_dependency_entries(cargo_text, &dependencies).unwrap()
+    }
+
+    #[test]
+    fn adds_in_sorted_position() {
+        let manifest = "[dependencies]\nanyhow = \"1\"\n# Serialization.\nserde = \"1\"\n";
+        assert_eq!(
+            add(manifest, "rand", "0.8"),
+            "[dependencies]\nanyhow = \"1\"\nrand = \"0.8\"\n# Serialization.\nserde = \"1\"\n"
+        );
+        let unsorted = "[dependencies]\nserde = \"1\"\nanyhow = \"1\"\n";
+        assert_eq!(
+            add(unsorted, "rand", "0.8"),
+            "[dependencies]\nserde = \"1\"\nanyhow = \"1\"\nrand = \"0.8\"\n"
+        );
+    }
+
+    #[test]
+    fn adds_after_a_multi_line_entry() {
+        let manifest = r#"[dependencies]
+tokio = { version = "1", features = [
+    "full",
+] }
+
+[dev-dependencies]
+proptest = "1"
+"#;
+        assert_eq!(
+            add(manifest, "zstd", "0.13"),
+            manifest.replace("] }\n", "] }\nzstd = \"0.13\"\n")
+        );
+    }
+
+    #[test]
+    fn array_lines_do_not_end_the_section() {
+        let manifest = r#"[dependencies]
+matrix = { version = "1", targets = [
+    ["x86_64", "linux"],
+    ["aarch64", "macos"],
+] }
+"#;
+        assert_eq!(
+            add(manifest, "zstd", "0.13"),
+            format!("{}zstd = \"0.13\"\n", manifest)
+        );
+    }
+
+    #[test]
+    fn adds_to_a_manifest_with_only_dependency_subtables() {
+        let manifest = "[package]\nname = \"demo\"\n\n[dependencies.serde]\nversion = \"1\"\n";
+        let edited = add(manifest, "anyhow", "1");
+        assert_eq!(edited.matches("[dependencies]").count(), 1);
+        assert_eq!(
+            edited,
+            "[package]\nname = \"demo\"\n\n[dependencies]\nanyhow = \"1\"\n\n\
+            [dependencies.serde]\nversion = \"1\"\n"
+        );
+    }
+
+    #[test]
+    fn adds_a_dependencies_table() {
+        let manifest = "[package]\nname = \"demo\"\n";
+        assert_eq!(
+            add(manifest, "anyhow", "1"),
+            "[package]\nname = \"demo\"\n\n[dependencies]\nanyhow = \"1\"\n"
+        );
+    }
+
+    #[test]
+    fn removing_leaves_the_rest_alone() {
+        let dependencies: Vec<_> = parse_cargo_file(MANIFEST.parse().unwrap())
+            .unwrap()
+            .into_iter()
+            .filter(|dependency| dependency.name != "serde")
+            .collect();
+        let edited = edit_dependency_entries(MANIFEST, &dependencies).unwrap();
+        assert_eq!(
+            edited,
+            MANIFEST.replace(
+                "serde = { version = \"1.0\", features = [\"derive\"] }\n",
+                ""
+            )
+        );
+    }
 }
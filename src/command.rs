@@ -1,10 +1,27 @@
-use crate::utils::Result;
+use carp2::config::DefaultFlags;
+use carp2::utils::Result;
 
 pub struct CommandFlags {
     pub strict: bool,
     pub only_strict: bool,
     pub entry: Option<String>,
+    pub limit: Option<usize>,
+    pub force: bool,
+    pub depth: Option<usize>,
+    pub invert: Option<String>,
+    pub duplicates: bool,
+    pub dry_run: bool,
+    pub remove: bool,
+    pub db: Option<String>,
+    pub vendor: Option<String>,
+    /// What `update --verify` runs after each update: `check`, `test` or a command line.
+    pub verify: Option<String>,
+    pub interactive: bool,
+    pub check: bool,
+    pub fix: bool,
+    pub since: Option<String>,
 }
+
 pub struct Command {
     pub name: String,
     pub flags: CommandFlags,
@@ -12,30 +29,128 @@ pub struct Command {
     pub raw_args: String,
 }
 
-pub fn parse_args(args: &[String]) -> Result<Command> {
-    assert!(args.len() > 0, "Expected at least one argument.");
+impl Command {
+    /// How the command is recorded in the manifest's history, e.g. `update serde`.
+    pub fn reason(&self) -> String {
+        format!("{} {}", self.name, self.raw_args).trim().to_owned()
+    }
+}
+
+/// Whether a command reads the project's carp config: the ones its default flags apply to and the
+/// ones its policy covers, like `impact` for bans and pins. The others keep working when the config
+/// is broken: `list`, `search`, `info`, `versions`, `explain` and `history` only show things, `rem`
+/// and `unused` only take dependencies out, `duplicates` only reads the lockfile, and `undo` and
+/// `bisect-update` put back requirements the manifest already had.
+pub fn uses_config(name: &str) -> bool {
+    matches!(
+        name,
+        "check"
+            | "add"
+            | "update"
+            | "impact"
+            | "tree"
+            | "audit"
+            | "licenses"
+            | "policy"
+            | "fmt"
+            | "lint"
+    )
+}
+
+fn parse_flag_value<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value '{}' for the flag '{}'.", value, flag).into())
+}
+
+/// Parses a command line, starting from the project's default flags. Boolean defaults are turned
+/// off with `--no-<flag>`, and value defaults are replaced by giving the flag.
+pub fn parse_args(args: &[String], defaults: &DefaultFlags) -> Result<Command> {
+    assert!(!args.is_empty(), "Expected at least one argument.");
 
     let mut filtered_args = vec![];
     let mut flags = CommandFlags {
-        strict: false,
-        only_strict: false,
+        strict: defaults.strict,
+        only_strict: defaults.only_strict,
         entry: None,
+        limit: None,
+        force: false,
+        depth: defaults.depth,
+        invert: None,
+        duplicates: false,
+        dry_run: defaults.dry_run,
+        remove: false,
+        db: defaults.db.clone(),
+        vendor: defaults.vendor.clone(),
+        verify: None,
+        interactive: false,
+        check: false,
+        fix: false,
+        since: None,
     };
+    // The flag waiting for its value, which is the argument right after it.
+    let mut value_flag: Option<&str> = None;
+    // How many arguments came before a `--verify=` command line, which has to be quoted as one
+    // argument so its words don't become arguments of carp's own.
+    let mut args_before_verify: Option<usize> = None;
 
     for arg in &args[1..] {
-        if arg.starts_with("-") {
-            match &arg[1..] {
+        if let Some(flag) = value_flag.take() {
+            match flag {
+                "entry" => flags.entry = Some(arg.to_owned()),
+                "limit" => flags.limit = Some(parse_flag_value(flag, arg)?),
+                "depth" => flags.depth = Some(parse_flag_value(flag, arg)?),
+                "invert" => flags.invert = Some(arg.to_owned()),
+                "db" => flags.db = Some(arg.to_owned()),
+                "vendor" => flags.vendor = Some(arg.to_owned()),
+                "since" => flags.since = Some(arg.to_owned()),
+                _ => unreachable!("Unhandled value flag '{}'.", flag),
+            }
+        } else if let Some(flag) = arg.strip_prefix('-') {
+            match flag {
                 "-strict" | "s" => flags.strict = true,
+                "-no-strict" => flags.strict = false,
                 "-only-strict" | "o" => flags.only_strict = true,
-                "-entry" | "e" => flags.entry = Some("".to_owned()),
+                "-no-only-strict" => flags.only_strict = false,
+                "-force" | "f" => flags.force = true,
+                "-entry" | "e" => value_flag = Some("entry"),
+                "-limit" | "l" => value_flag = Some("limit"),
+                "-depth" | "d" => value_flag = Some("depth"),
+                "-invert" | "i" => value_flag = Some("invert"),
+                "-duplicates" => flags.duplicates = true,
+                "-db" => value_flag = Some("db"),
+                "-vendor" => value_flag = Some("vendor"),
+                "-since" => value_flag = Some("since"),
+                "-dry-run" | "n" => flags.dry_run = true,
+                "-no-dry-run" => flags.dry_run = false,
+                "-remove" | "r" => flags.remove = true,
+                "-interactive" => flags.interactive = true,
+                "-check" => flags.check = true,
+                "-fix" => flags.fix = true,
+                "-verify" => flags.verify = Some("check".to_owned()),
+                _ if flag.starts_with("-verify=") => {
+                    let verify = &flag["-verify=".len()..];
+                    args_before_verify =
+                        (!matches!(verify, "check" | "test")).then_some(filtered_args.len());
+                    flags.verify = Some(verify.to_owned())
+                }
                 unknown_flag => return Err(format!("Unknown flag '{}'.", unknown_flag).into()),
             }
         } else {
-            if flags.entry.is_some() {
-                flags.entry = Some(arg.to_owned());
-            } else {
-                filtered_args.push(arg.to_owned())
-            }
+            filtered_args.push(arg.to_owned())
+        }
+    }
+    if let Some(flag) = value_flag {
+        return Err(format!("Expected a value after the flag '{}'.", flag).into());
+    }
+    if let Some(count) = args_before_verify {
+        if filtered_args.len() > count {
+            return Err(format!(
+                "Quote the whole verify command, e.g. --verify=\"{} {}\".",
+                flags.verify.unwrap_or_default(),
+                filtered_args[count..].join(" ")
+            )
+            .into());
         }
     }
 
@@ -52,3 +167,36 @@ pub fn parse_args(args: &[String]) -> Result<Command> {
         raw_args,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_the_config_covers_read_it() {
+        for name in [
+            "check", "add", "update", "impact", "licenses", "policy", "lint",
+        ] {
+            assert!(uses_config(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn commands_that_only_show_or_take_out_skip_the_config() {
+        for name in [
+            "list",
+            "search",
+            "info",
+            "versions",
+            "explain",
+            "history",
+            "rem",
+            "unused",
+            "duplicates",
+            "undo",
+            "bisect-update",
+        ] {
+            assert!(!uses_config(name), "{}", name);
+        }
+    }
+}
@@ -1,13 +1,88 @@
-use semver::VersionReq;
+use crate::utils::Result;
+use fs2::FileExt;
+use semver::{Version, VersionReq};
 use std::env;
+use std::fs::{self, File, OpenOptions};
 use std::path::PathBuf;
 
+/// The tables of a manifest that declare dependencies, at the top level or under a `[target]`.
+pub const DEPENDENCY_SECTIONS: [&str; 3] =
+    ["dependencies", "dev-dependencies", "build-dependencies"];
+
 pub fn get_cargo_path() -> PathBuf {
     let mut path = env::current_dir().unwrap();
     path.push("cargo.toml");
     path
 }
 
+pub fn get_package_dir() -> PathBuf {
+    env::current_dir().unwrap()
+}
+
+pub fn get_config_path() -> PathBuf {
+    let mut path = env::current_dir().unwrap();
+    path.push("carp.toml");
+    path
+}
+
+/// Where earlier states of the manifest are kept for `carp undo`.
+pub fn get_history_dir() -> PathBuf {
+    let mut path = env::current_dir().unwrap();
+    path.push(".carp");
+    path.push("history");
+    path
+}
+
+/// The file carp locks while it edits the manifest.
+pub fn get_manifest_lock_path() -> PathBuf {
+    let mut path = env::current_dir().unwrap();
+    path.push(".carp");
+    path.push("lock");
+    path
+}
+
+pub fn get_lock_path() -> PathBuf {
+    let mut path = env::current_dir().unwrap();
+    path.push("Cargo.lock");
+    path
+}
+
+pub fn get_cargo_home() -> PathBuf {
+    env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cargo")))
+        .unwrap_or_else(|| PathBuf::from(".cargo"))
+}
+
+/// A lock on editing the package's manifest, released when it's dropped.
+pub struct ManifestLock {
+    _file: File,
+}
+
+/// Locks `.carp/lock` in the package's directory, so only one carp edits the manifest at a time,
+/// saying so if it has to wait for another one.
+pub fn lock_manifest() -> Result<ManifestLock> {
+    let path = get_manifest_lock_path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)?;
+    if file.try_lock_exclusive().is_err() {
+        eprintln!("Waiting for another carp to finish editing the manifest...");
+        file.lock_exclusive()?;
+    }
+    Ok(ManifestLock { _file: file })
+}
+
+pub fn get_advisory_db_path() -> PathBuf {
+    get_cargo_home().join("advisory-db")
+}
+
 pub fn version_req_str(version_req: &VersionReq) -> String {
     let string = format!("{}", version_req);
     if string.contains("^") {
@@ -16,3 +91,14 @@ pub fn version_req_str(version_req: &VersionReq) -> String {
         string
     }
 }
+
+/// The semver-compatible series a version belongs to, e.g. `1.x`, `0.8.x` or `0.0.3`.
+pub fn compatible_series(version: &Version) -> String {
+    if version.major > 0 {
+        format!("{}.x", version.major)
+    } else if version.minor > 0 {
+        format!("0.{}.x", version.minor)
+    } else {
+        format!("0.0.{}", version.patch)
+    }
+}
@@ -0,0 +1,448 @@
+use crate::cargoreader::read_cargo_file;
+use crate::common::{get_cargo_path, get_config_path};
+use crate::dependency::requirements_overlap;
+use crate::lint::LintId;
+use crate::utils::Result;
+use semver::{Version, VersionReq};
+use std::collections::BTreeMap;
+use std::fs;
+use toml::value::Table;
+use toml::Value;
+
+#[derive(Debug, Clone, Default)]
+pub struct LicenseConfig {
+    /// SPDX license identifiers dependencies may use. Empty allows anything not denied.
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    /// Whether crates whose license can't be read as an SPDX expression pass the check.
+    pub allow_unknown: bool,
+}
+
+/// A crate from the `banned` list, written `name` to ban every version or `name@requirement` to
+/// ban only the matching ones.
+#[derive(Debug, Clone)]
+pub struct BannedCrate {
+    pub name: String,
+    pub version_req: Option<VersionReq>,
+}
+
+impl BannedCrate {
+    /// Whether the ban covers a version of a crate. An unknown version is only covered by a ban on
+    /// every version.
+    pub fn covers(&self, name: &str, version: Option<&Version>) -> bool {
+        self.name == name
+            && match (&self.version_req, version) {
+                (None, _) => true,
+                (Some(version_req), Some(version)) => version_req.matches(version),
+                (Some(_), None) => false,
+            }
+    }
+
+    /// Whether the ban covers any version a requirement allows.
+    pub fn covers_requirement(&self, name: &str, version_req: &VersionReq) -> bool {
+        self.name == name
+            && self
+                .version_req
+                .as_ref()
+                .is_none_or(|banned| requirements_overlap(banned, version_req))
+    }
+}
+
+impl std::fmt::Display for BannedCrate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.version_req {
+            Some(version_req) => write!(f, "{}@{}", self.name, version_req),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// How `carp fmt` writes dependencies that need a table: `{ version = "1", ... }` on one line, or
+/// a `[dependencies.name]` section of their own.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TableStyle {
+    #[default]
+    Inline,
+    Expanded,
+}
+
+/// Flags the commands that read the config start from, from the `[defaults]` table. Flags on the
+/// command line win, with `--no-<flag>` turning off a boolean one.
+#[derive(Debug, Clone, Default)]
+pub struct DefaultFlags {
+    pub strict: bool,
+    pub only_strict: bool,
+    pub dry_run: bool,
+    pub depth: Option<usize>,
+    pub db: Option<String>,
+    pub vendor: Option<String>,
+}
+
+/// carp's settings for a project, from a `carp.toml` next to the manifest or the manifest's
+/// `[package.metadata.carp]` table.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// Crates `check` doesn't report unless they're named on the command line.
+    pub ignore: Vec<String>,
+    /// Crates `add` refuses to add and `policy check` reports.
+    pub banned: Vec<BannedCrate>,
+    /// Trusted crates: `add` warns about names a typo away from them, and never about them.
+    pub allowlist: Vec<String>,
+    /// Registries, by index URL or by the name cargo knows them as, that crates may come from.
+    /// Empty allows any.
+    pub registries: Vec<String>,
+    /// Ranges `update` never moves a crate out of.
+    pub pinned: BTreeMap<String, VersionReq>,
+    /// The lowest version each crate may be required at.
+    pub minimum: BTreeMap<String, Version>,
+    pub defaults: DefaultFlags,
+    pub licenses: LicenseConfig,
+    pub tables: TableStyle,
+    /// Lints `carp lint` never reports.
+    pub allowed_lints: Vec<LintId>,
+}
+
+impl Config {
+    pub fn ban_for(&self, name: &str, version: Option<&Version>) -> Option<&BannedCrate> {
+        self.banned
+            .iter()
+            .find(|banned| banned.covers(name, version))
+    }
+
+    pub fn ban_for_requirement(
+        &self,
+        name: &str,
+        version_req: &VersionReq,
+    ) -> Option<&BannedCrate> {
+        self.banned
+            .iter()
+            .find(|banned| banned.covers_requirement(name, version_req))
+    }
+
+    pub fn is_ignored(&self, name: &str) -> bool {
+        self.ignore.iter().any(|ignored| ignored == name)
+    }
+}
+
+fn parse_table<'a>(value: &'a Value, key: &str) -> Result<&'a Table> {
+    value
+        .as_table()
+        .ok_or(format!("The '{}' key is not a table.", key).into())
+}
+
+fn check_keys(table: &Table, prefix: Option<&str>, known: &[&str]) -> Result<()> {
+    match table.keys().find(|key| !known.contains(&key.as_str())) {
+        Some(key) => Err(match prefix {
+            Some(prefix) => format!("Unknown key '{}.{}'.", prefix, key),
+            None => format!("Unknown key '{}'.", key),
+        }
+        .into()),
+        None => Ok(()),
+    }
+}
+
+fn parse_string_list(value: Option<&Value>, key: &str) -> Result<Vec<String>> {
+    match value {
+        Some(value) => value
+            .as_array()
+            .ok_or(format!("The '{}' key is not an array.", key))?
+            .iter()
+            .map(|item| {
+                item.as_str().map(str::to_owned).ok_or(
+                    format!("The '{}' key contains something that is not a string.", key).into(),
+                )
+            })
+            .collect(),
+        None => Ok(vec![]),
+    }
+}
+
+/// Parses a table of crate names to strings with `parse`, which gets the full key for its errors.
+fn parse_crate_table<T>(
+    value: Option<&Value>,
+    key: &str,
+    parse: impl Fn(&str, &str) -> Result<T>,
+) -> Result<BTreeMap<String, T>> {
+    let mut parsed = BTreeMap::new();
+    if let Some(value) = value {
+        for (name, item) in parse_table(value, key)? {
+            let item_key = format!("{}.{}", key, name);
+            let item = item
+                .as_str()
+                .ok_or(format!("The '{}' key is not a string.", item_key))?;
+            parsed.insert(name.to_owned(), parse(&item_key, item)?);
+        }
+    }
+    Ok(parsed)
+}
+
+fn parse_banned(value: Option<&Value>) -> Result<Vec<BannedCrate>> {
+    parse_string_list(value, "banned")?
+        .iter()
+        .map(|entry| match entry.split_once('@') {
+            Some((name, version_req)) => Ok(BannedCrate {
+                name: name.to_owned(),
+                version_req: Some(version_req.parse().map_err(|_| {
+                    format!(
+                        "The 'banned' key has an invalid version requirement in '{}'.",
+                        entry
+                    )
+                })?),
+            }),
+            None => Ok(BannedCrate {
+                name: entry.to_owned(),
+                version_req: None,
+            }),
+        })
+        .collect()
+}
+
+fn parse_bool(table: &Table, prefix: &str, key: &str) -> Result<bool> {
+    match table.get(key) {
+        Some(value) => value
+            .as_bool()
+            .ok_or(format!("The '{}.{}' key is not a boolean.", prefix, key).into()),
+        None => Ok(false),
+    }
+}
+
+fn parse_count(table: &Table, prefix: &str, key: &str) -> Result<Option<usize>> {
+    match table.get(key) {
+        Some(value) => value
+            .as_integer()
+            .and_then(|count| usize::try_from(count).ok())
+            .map(Some)
+            .ok_or(format!("The '{}.{}' key is not a positive integer.", prefix, key).into()),
+        None => Ok(None),
+    }
+}
+
+fn parse_string(table: &Table, prefix: &str, key: &str) -> Result<Option<String>> {
+    match table.get(key) {
+        Some(value) => value
+            .as_str()
+            .map(|string| Some(string.to_owned()))
+            .ok_or(format!("The '{}.{}' key is not a string.", prefix, key).into()),
+        None => Ok(None),
+    }
+}
+
+fn parse_defaults(value: &Value) -> Result<DefaultFlags> {
+    let table = parse_table(value, "defaults")?;
+    check_keys(
+        table,
+        Some("defaults"),
+        &["strict", "only-strict", "dry-run", "depth", "db", "vendor"],
+    )?;
+    Ok(DefaultFlags {
+        strict: parse_bool(table, "defaults", "strict")?,
+        only_strict: parse_bool(table, "defaults", "only-strict")?,
+        dry_run: parse_bool(table, "defaults", "dry-run")?,
+        depth: parse_count(table, "defaults", "depth")?,
+        db: parse_string(table, "defaults", "db")?,
+        vendor: parse_string(table, "defaults", "vendor")?,
+    })
+}
+
+pub fn parse_config(config_value: &Value) -> Result<Config> {
+    let table = parse_table(config_value, "carp")?;
+    check_keys(
+        table,
+        None,
+        &[
+            "ignore",
+            "banned",
+            "allowlist",
+            "registries",
+            "pinned",
+            "minimum",
+            "defaults",
+            "licenses",
+            "fmt",
+            "lint",
+        ],
+    )?;
+
+    let mut config = Config {
+        ignore: parse_string_list(table.get("ignore"), "ignore")?,
+        banned: parse_banned(table.get("banned"))?,
+        allowlist: parse_string_list(table.get("allowlist"), "allowlist")?,
+        registries: parse_string_list(table.get("registries"), "registries")?,
+        pinned: parse_crate_table(table.get("pinned"), "pinned", |key, version_req| {
+            version_req.parse().map_err(|_| {
+                format!(
+                    "The '{}' key is not a valid version requirement: '{}'.",
+                    key, version_req
+                )
+                .into()
+            })
+        })?,
+        minimum: parse_crate_table(table.get("minimum"), "minimum", |key, version| {
+            version.parse().map_err(|_| {
+                format!(
+                    "The '{}' key is not a full version like \"1.2.3\": '{}'.",
+                    key, version
+                )
+                .into()
+            })
+        })?,
+        ..Config::default()
+    };
+    if let Some(defaults) = table.get("defaults") {
+        config.defaults = parse_defaults(defaults)?;
+    }
+    if let Some(fmt) = table.get("fmt") {
+        let fmt = parse_table(fmt, "fmt")?;
+        check_keys(fmt, Some("fmt"), &["tables"])?;
+        config.tables = match parse_string(fmt, "fmt", "tables")?.as_deref() {
+            None | Some("inline") => TableStyle::Inline,
+            Some("expanded") => TableStyle::Expanded,
+            Some(other) => {
+                return Err(format!(
+                    "The 'fmt.tables' key is '{}', but it can only be \"inline\" or \"expanded\".",
+                    other
+                )
+                .into())
+            }
+        };
+    }
+    if let Some(lint) = table.get("lint") {
+        check_keys(parse_table(lint, "lint")?, Some("lint"), &["allow"])?;
+        config.allowed_lints = parse_string_list(lint.get("allow"), "lint.allow")?
+            .iter()
+            .map(|name| {
+                LintId::from_name(name)
+                    .ok_or(format!("The 'lint.allow' key has an unknown lint '{}'.", name).into())
+            })
+            .collect::<Result<_>>()?;
+    }
+    if let Some(licenses) = table.get("licenses") {
+        let licenses = parse_table(licenses, "licenses")?;
+        check_keys(
+            licenses,
+            Some("licenses"),
+            &["allow", "deny", "allow-unknown"],
+        )?;
+        config.licenses = LicenseConfig {
+            allow: parse_string_list(licenses.get("allow"), "licenses.allow")?,
+            deny: parse_string_list(licenses.get("deny"), "licenses.deny")?,
+            allow_unknown: parse_bool(licenses, "licenses", "allow-unknown")?,
+        };
+    }
+    Ok(config)
+}
+
+/// Reads the project's config, preferring `carp.toml` over the manifest's metadata, and falling
+/// back to the defaults if there's neither. Errors say which of the two was being read.
+pub fn read_config() -> Result<Config> {
+    let config_path = get_config_path();
+    if config_path.exists() {
+        let config_value: Value = fs::read_to_string(&config_path)?.parse()?;
+        return parse_config(&config_value)
+            .map_err(|err| format!("{} ({})", err, config_path.display()).into());
+    }
+    if !get_cargo_path().exists() {
+        return Ok(Config::default());
+    }
+    match read_cargo_file()?
+        .get("package")
+        .and_then(|package| package.get("metadata"))
+        .and_then(|metadata| metadata.get("carp"))
+    {
+        Some(config_value) => parse_config(config_value).map_err(|err| {
+            format!(
+                "{} ([package.metadata.carp] in {})",
+                err,
+                get_cargo_path().display()
+            )
+            .into()
+        }),
+        None => Ok(Config::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Config> {
+        parse_config(&text.parse::<Value>().unwrap())
+    }
+
+    #[test]
+    fn check_keys_names_the_unknown_key() {
+        let table: Value = "known = 1\nother = 2".parse().unwrap();
+        let table = table.as_table().unwrap();
+        assert!(check_keys(table, None, &["known", "other"]).is_ok());
+        assert_eq!(
+            check_keys(table, None, &["known"]).unwrap_err().to_string(),
+            "Unknown key 'other'."
+        );
+        assert_eq!(
+            check_keys(table, Some("defaults"), &["known"])
+                .unwrap_err()
+                .to_string(),
+            "Unknown key 'defaults.other'."
+        );
+    }
+
+    #[test]
+    fn parses_a_full_config() {
+        let config = parse(
+            r#"
+ignore = ["rand"]
+banned = ["openssl", "time@<0.2"]
+allowlist = ["serde"]
+registries = ["crates-io"]
+pinned = { tokio = "~1.20" }
+minimum = { regex = "1.5.5" }
+
+[defaults]
+strict = true
+depth = 3
+
+[licenses]
+deny = ["GPL-3.0"]
+allow-unknown = true
+
+[fmt]
+tables = "expanded"
+
+[lint]
+allow = ["exact-pin"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.ignore, vec!["rand"]);
+        assert!(config.ban_for("openssl", None).is_some());
+        assert!(config
+            .ban_for("time", Some(&Version::new(0, 1, 0)))
+            .is_some());
+        assert!(config
+            .ban_for("time", Some(&Version::new(0, 3, 0)))
+            .is_none());
+        assert_eq!(config.allowlist, vec!["serde"]);
+        assert!(config.pinned["tokio"].matches(&Version::new(1, 20, 4)));
+        assert_eq!(config.minimum["regex"], Version::new(1, 5, 5));
+        assert!(config.defaults.strict && !config.defaults.dry_run);
+        assert_eq!(config.defaults.depth, Some(3));
+        assert_eq!(config.licenses.deny, vec!["GPL-3.0"]);
+        assert!(config.licenses.allow_unknown);
+        assert_eq!(config.tables, TableStyle::Expanded);
+        assert_eq!(config.allowed_lints, vec![LintId::ExactPin]);
+    }
+
+    #[test]
+    fn errors_point_at_the_key() {
+        let error = |text: &str| parse(text).unwrap_err().to_string();
+        assert_eq!(error("ignored = []"), "Unknown key 'ignored'.");
+        assert_eq!(
+            error("[defaults]\nlimit = 5"),
+            "Unknown key 'defaults.limit'."
+        );
+        assert!(error("pinned = { tokio = \"one\" }").contains("'pinned.tokio'"));
+        assert!(error("minimum = { regex = \"1.5\" }").contains("'minimum.regex'"));
+        assert!(error("[lint]\nallow = [\"nope\"]").contains("'lint.allow'"));
+        assert!(error("[fmt]\ntables = \"wide\"").contains("'fmt.tables'"));
+    }
+}
@@ -1,31 +1,147 @@
-use crate::dependency::{get_versions_from_str, Dependency};
-use crate::utils::Result;
-use crates_index::{Crate, Index};
+use crate::common::{get_cargo_home, version_req_str};
+use crate::dependency::{get_version_from_version_req, get_versions_from_str, Dependency};
+use crate::utils::{edit_distance, Result};
+use crates_index::{Crate, Index, SparseIndex, CRATES_IO_HTTP_INDEX, INDEX_GIT_URL};
+use git2::{ObjectType, Repository, TreeWalkMode, TreeWalkResult};
+use rayon::prelude::*;
 use semver::{Version, VersionReq};
+use std::fs;
+use std::path::{Path, PathBuf};
 
-fn get_crate_latest_version(crte: &Crate) -> Result<Version> {
-    let crate_latest: Version = crte
-        .highest_stable_version()
-        .ok_or(format!(
+pub const INDEX_URL: &str = "https://github.com/rust-lang/crates.io-index";
+/// How the directories cargo keeps the crates.io index in start: the sparse index's cache, then the
+/// git checkout older versions of cargo used. The rest of the name is a hash that changes between
+/// cargo versions.
+const SPARSE_INDEX_PREFIX: &str = "index.crates.io-";
+const GIT_INDEX_PREFIX: &str = "github.com-";
+
+/// A crate read from the index once, with every version string parsed up front.
+pub struct IndexedCrate {
+    pub crte: Crate,
+    /// Parsed versions, in the same order as `crte.versions()`.
+    pub versions: Vec<Version>,
+    pub latest: Option<Version>,
+}
+
+impl IndexedCrate {
+    pub fn new(crte: Crate) -> Result<IndexedCrate> {
+        let versions = crte
+            .versions()
+            .iter()
+            .map(|crate_version| crate_version.version().parse())
+            .collect::<std::result::Result<Vec<Version>, _>>()?;
+        let latest = versions
+            .iter()
+            .zip(crte.versions())
+            .filter(|(version, crate_version)| version.pre.is_empty() && !crate_version.is_yanked())
+            .map(|(version, _)| version)
+            .max()
+            .cloned();
+        Ok(IndexedCrate {
+            crte,
+            versions,
+            latest,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        self.crte.name()
+    }
+
+    pub fn latest_version(&self) -> Result<&Version> {
+        Ok(self.latest.as_ref().ok_or(format!(
             "Could not find the latest version for crate '{}'.",
-            crte.name()
-        ))?
-        .version()
-        .parse()?;
-    Ok(crate_latest)
+            self.name()
+        ))?)
+    }
+
+    /// The position of the latest version in `versions` and `crte.versions()`.
+    pub fn latest_index(&self) -> Option<usize> {
+        let latest = self.latest.as_ref()?;
+        self.versions.iter().position(|version| version == latest)
+    }
+
+    /// The position of the version cargo would pick for a requirement: the highest one that
+    /// matches and isn't yanked.
+    pub fn select_version(&self, version_req: &VersionReq) -> Option<usize> {
+        self.versions
+            .iter()
+            .enumerate()
+            .filter(|(i, version)| {
+                version_req.matches(version) && !self.crte.versions()[*i].is_yanked()
+            })
+            .max_by_key(|(_, version)| *version)
+            .map(|(i, _)| i)
+    }
+
+    pub fn has_version(&self, version_req: &VersionReq) -> bool {
+        self.versions
+            .iter()
+            .any(|version| version_req.matches(version))
+    }
+}
+
+pub fn load_crate(index: &Index, name: &str) -> Result<IndexedCrate> {
+    let crte = index
+        .crate_(name)
+        .ok_or(format!("Crate '{}' not found.", name))?;
+    IndexedCrate::new(crte)
+}
+
+pub fn load_local_crate(index: &LocalIndex, name: &str) -> Result<IndexedCrate> {
+    let crte = index
+        .crate_(name)
+        .ok_or(format!("Crate '{}' not found.", name))?;
+    IndexedCrate::new(crte)
 }
 
-fn compare_crate_version(current_version: &VersionReq, crte: &Crate) -> Result<bool> {
-    Ok(current_version.matches(&get_crate_latest_version(crte)?))
+/// Looks up every named crate in the index, spread across threads, with `None` for the ones it
+/// doesn't have. Each thread opens its own handle to the index, since the underlying git
+/// repository can't be shared between them.
+pub fn find_crates(index: &Index, names: &[&str]) -> Result<Vec<Option<IndexedCrate>>> {
+    let (index_path, index_url) = (index.path(), index.url());
+    let found: std::result::Result<Vec<_>, String> = names
+        .par_iter()
+        .map_init(
+            || Index::with_path(index_path, index_url),
+            |thread_index, name| {
+                let thread_index = thread_index.as_ref().map_err(|err| err.to_string())?;
+                thread_index
+                    .crate_(name)
+                    .map(IndexedCrate::new)
+                    .transpose()
+                    .map_err(|err| err.to_string())
+            },
+        )
+        .collect();
+    Ok(found?)
 }
 
-fn compare_crate_version_strict(current_version: &Version, crte: &Crate) -> Result<bool> {
-    Ok(current_version == &get_crate_latest_version(crte)?)
+/// Loads every named crate from the index, spread across threads like `find_crates`.
+pub fn load_crates(index: &Index, names: &[&str]) -> Result<Vec<IndexedCrate>> {
+    names
+        .iter()
+        .zip(find_crates(index, names)?)
+        .map(|(name, crte)| crte.ok_or(format!("Crate '{}' not found.", name).into()))
+        .collect()
+}
+
+/// Whether a requirement allows the latest version, or starts past it, as a prerelease of the next
+/// version does, e.g. `2.0.0-rc.1` when the latest is 1.9.
+fn compare_crate_version(current_version: &VersionReq, crte: &IndexedCrate) -> Result<bool> {
+    let latest = crte.latest_version()?;
+    Ok(current_version.matches(latest)
+        || get_version_from_version_req(current_version).is_some_and(|floor| &floor >= latest))
+}
+
+/// Whether a requirement's floor is the latest version or past it, so updating never goes back.
+fn compare_crate_version_strict(current_version: &Version, crte: &IndexedCrate) -> Result<bool> {
+    Ok(current_version >= crte.latest_version()?)
 }
 
 pub fn get_crate_latest_versions(crte: &Crate) -> Result<(VersionReq, Option<Version>)> {
     let crate_latest_str = crte
-        .highest_stable_version()
+        .highest_normal_version()
         .ok_or(format!(
             "Could not find the latest version for crate '{}'.",
             crte.name()
@@ -35,70 +151,720 @@ pub fn get_crate_latest_versions(crte: &Crate) -> Result<(VersionReq, Option<Ver
     Ok(versions)
 }
 
+fn is_out_of_date(
+    strict: bool,
+    only_strict: bool,
+    dependency: &Dependency,
+    crte: &IndexedCrate,
+) -> Result<bool> {
+    let up_to_date = if only_strict {
+        compare_crate_version_strict(
+            dependency.version.as_ref().ok_or(format!(
+                "The requirement of '{}' ({}) has no lower bound to compare strictly.",
+                &dependency.name,
+                version_req_str(&dependency.version_req)
+            ))?,
+            crte,
+        )?
+    } else if strict {
+        if let Some(version) = &dependency.version {
+            compare_crate_version_strict(version, crte)?
+        } else {
+            compare_crate_version(&dependency.version_req, crte)?
+        }
+    } else {
+        compare_crate_version(&dependency.version_req, crte)?
+    };
+    Ok(!up_to_date)
+}
+
 pub fn out_of_date_dependencies(
     strict: bool,
     only_strict: bool,
     index: &Index,
-    dependencies: &Vec<Dependency>,
+    dependencies: &[Dependency],
 ) -> Result<Vec<(Dependency, Version)>> {
-    let crate_compares: Result<Vec<_>> = dependencies
+    let names: Vec<_> = dependencies
         .iter()
-        .map(|dependency| {
-            let crate_from_dependency = index
-                .crate_(&dependency.name)
-                .ok_or(format!("Crate '{}' not found.", &dependency.name))?;
-            if only_strict {
-                compare_crate_version_strict(
-                    dependency.version.as_ref().ok_or(format!(
-                        "Dependency version for '{}' is not specific enough to compare strictly.",
-                        &dependency.name
-                    ))?,
-                    &crate_from_dependency,
-                )
-                .map(|comp| (comp, crate_from_dependency))
-            } else if strict {
-                if let Some(version) = &dependency.version {
-                    compare_crate_version_strict(version, &crate_from_dependency)
-                        .map(|comp| (comp, crate_from_dependency))
-                } else {
-                    compare_crate_version(&dependency.version_req, &crate_from_dependency)
-                        .map(|comp| (comp, crate_from_dependency))
-                }
-            } else {
-                compare_crate_version(&dependency.version_req, &crate_from_dependency)
-                    .map(|comp| (comp, crate_from_dependency))
-            }
+        .map(|dependency| dependency.name.as_str())
+        .collect();
+    let crates = load_crates(index, &names)?;
+
+    let mut out_of_date = vec![];
+    for (dependency, crte) in dependencies.iter().zip(crates) {
+        if is_out_of_date(strict, only_strict, dependency, &crte)? {
+            out_of_date.push((dependency.to_owned(), crte.latest_version()?.to_owned()));
+        }
+    }
+    Ok(out_of_date)
+}
+
+pub enum FeatureCheck {
+    Compatible,
+    /// The target version lacks some enabled features, but this older one still has them all.
+    Fallback {
+        missing: Vec<String>,
+        version: Version,
+    },
+    /// No version newer than the current one has every enabled feature.
+    Incompatible {
+        missing: Vec<String>,
+    },
+}
+
+/// Whether a version of a crate has the implicit feature an optional dependency of it makes, which
+/// it doesn't if any of its features enables that dependency with `dep:<name>`.
+fn has_implicit_feature(crate_version: &crates_index::Version, name: &str) -> bool {
+    let dep_syntax = format!("dep:{}", name);
+    crate_version
+        .dependencies()
+        .iter()
+        .any(|dependency| dependency.is_optional() && dependency.name() == name)
+        && !crate_version
+            .features()
+            .values()
+            .flatten()
+            .any(|enabled| *enabled == dep_syntax)
+}
+
+/// The enabled features of a dependency that a version of its crate doesn't have, counting
+/// optional dependencies as the implicit features they are.
+fn missing_features(features: &[String], crte: &IndexedCrate, position: usize) -> Vec<String> {
+    let crate_version = &crte.crte.versions()[position];
+    features
+        .iter()
+        .filter(|feature| {
+            !crate_version.features().contains_key(*feature)
+                && !has_implicit_feature(crate_version, feature)
         })
+        .cloned()
+        .collect()
+}
+
+/// The version a dependency is at, for telling whether another one is an update: the one its
+/// requirement names, or else the lowest one it allows.
+fn current_version(dependency: &Dependency, crte: &IndexedCrate) -> Option<Version> {
+    dependency.version.clone().or_else(|| {
+        crte.versions
+            .iter()
+            .filter(|version| dependency.version_req.matches(version))
+            .min()
+            .cloned()
+    })
+}
+
+/// Why a version picked by hand can't be what a dependency is updated to, if it can't. It has to
+/// be a published version that isn't yanked, newer than the current one, inside the pin the config
+/// sets for the dependency if there is one, and have every feature the dependency enables.
+pub fn picked_version_problem(
+    dependency: &Dependency,
+    crte: &IndexedCrate,
+    version: &Version,
+    pin: Option<&VersionReq>,
+) -> Option<String> {
+    let position = match crte.versions.iter().position(|known| known == version) {
+        Some(position) => position,
+        None => {
+            return Some(format!(
+                "'{}' is not a published version of {}.",
+                version,
+                crte.name()
+            ))
+        }
+    };
+    if crte.crte.versions()[position].is_yanked() {
+        return Some(format!("({}) is yanked.", version));
+    }
+    if let Some(current) = current_version(dependency, crte).filter(|current| version <= current) {
+        return Some(format!("({}) is not newer than ({}).", version, current));
+    }
+    if let Some(pin) = pin.filter(|pin| !pin.matches(version)) {
+        return Some(format!(
+            "{} is pinned to ({}) by the project's carp config.",
+            dependency.name,
+            version_req_str(pin)
+        ));
+    }
+    let missing = missing_features(&dependency.features(), crte, position);
+    if !missing.is_empty() {
+        return Some(format!(
+            "({}) doesn't have the feature(s) '{}'.",
+            version,
+            missing.join(",")
+        ));
+    }
+    None
+}
+
+/// Checks that every feature a dependency enables still exists in the version it would be updated
+/// to, looking for the newest version between the current and target ones that has them if not.
+pub fn check_update_features(
+    index: &Index,
+    updates: &[(Dependency, Version)],
+) -> Result<Vec<FeatureCheck>> {
+    let names: Vec<_> = updates
+        .iter()
+        .map(|(dependency, _)| dependency.crate_name())
         .collect();
-    dependencies
-        .into_iter()
-        .zip(crate_compares?)
-        .filter_map(
-            |(dependency, (compare, crte))| match get_crate_latest_version(&crte) {
-                Ok(latest_version) => {
-                    if !compare {
-                        Some(Ok((dependency.to_owned(), latest_version)))
-                    } else {
-                        None
-                    }
-                }
-                Err(err) => Some(Err(err)),
+    let crates = load_crates(index, &names)?;
+
+    let mut checks = vec![];
+    for ((dependency, target), crte) in updates.iter().zip(crates) {
+        let features = dependency.features();
+        let target_position = crte
+            .versions
+            .iter()
+            .position(|version| version == target)
+            .ok_or(format!(
+                "Version '{}' of crate '{}' not found.",
+                target, dependency.name
+            ))?;
+        let missing = missing_features(&features, &crte, target_position);
+        if missing.is_empty() {
+            checks.push(FeatureCheck::Compatible);
+            continue;
+        }
+
+        // The fallback has to be newer than the current version, or it wouldn't be an update at
+        // all.
+        let current = current_version(dependency, &crte);
+        let fallback = (0..crte.versions.len())
+            .filter(|position| {
+                let version = &crte.versions[*position];
+                version < target
+                    && current.as_ref().is_none_or(|current| version > current)
+                    && version.pre.is_empty()
+                    && !crte.crte.versions()[*position].is_yanked()
+                    && missing_features(&features, &crte, *position).is_empty()
+            })
+            .max_by_key(|position| &crte.versions[*position]);
+        checks.push(match fallback {
+            Some(position) => FeatureCheck::Fallback {
+                missing,
+                version: crte.versions[position].clone(),
             },
-        )
-        .collect()
+            None => FeatureCheck::Incompatible { missing },
+        });
+    }
+    Ok(checks)
 }
 
-pub fn crate_has_version(version: &VersionReq, crte: &Crate) -> Result<bool> {
-    for crate_version in crte.versions() {
-        let crate_semver: Version = crate_version.version().parse()?;
-        if version.matches(&crate_semver) {
-            return Ok(true);
+/// What a requirement matches in a crate, as positions in `versions` and `crte.versions()`.
+pub struct RequirementMatches {
+    /// Every matching version, newest first, yanked ones included.
+    pub matching: Vec<usize>,
+    /// The version cargo would select.
+    pub selected: Option<usize>,
+    /// The newest version older than every match that doesn't match.
+    pub below: Option<usize>,
+    /// The oldest version newer than every match that doesn't match.
+    pub above: Option<usize>,
+}
+
+/// Works out what a requirement matches. When nothing does, the versions just outside it are the
+/// ones on either side of `floor`, the lowest version the requirement names.
+pub fn requirement_matches(
+    crte: &IndexedCrate,
+    version_req: &VersionReq,
+    floor: Option<&Version>,
+) -> RequirementMatches {
+    let mut matching: Vec<usize> = (0..crte.versions.len())
+        .filter(|position| version_req.matches(&crte.versions[*position]))
+        .collect();
+    matching.sort_by(|a, b| crte.versions[*b].cmp(&crte.versions[*a]));
+
+    let (low, high) = match (matching.last(), matching.first()) {
+        (Some(&lowest), Some(&highest)) => {
+            (Some(&crte.versions[lowest]), Some(&crte.versions[highest]))
         }
+        _ => (floor, floor),
+    };
+    let outside = |position: &usize| !version_req.matches(&crte.versions[*position]);
+    let below = low.and_then(|low| {
+        (0..crte.versions.len())
+            .filter(outside)
+            .filter(|position| crte.versions[*position] < *low)
+            .max_by_key(|position| &crte.versions[*position])
+    });
+    let above = high.and_then(|high| {
+        (0..crte.versions.len())
+            .filter(outside)
+            .filter(|position| {
+                crte.versions[*position] > *high
+                    || (matching.is_empty() && crte.versions[*position] == *high)
+            })
+            .min_by_key(|position| &crte.versions[*position])
+    });
+
+    RequirementMatches {
+        matching,
+        selected: crte.select_version(version_req),
+        below,
+        above,
     }
-    Ok(false)
 }
 
 pub fn get_index() -> Result<Index> {
     let index = Index::new_cargo_default()?;
     Ok(index)
 }
+
+/// The crates.io index as cargo keeps it on disk, read without going to the network.
+pub enum LocalIndex {
+    /// The cache of the sparse index, which only has the crates cargo has looked up.
+    Sparse {
+        index: SparseIndex,
+        path: PathBuf,
+    },
+    Git(Index),
+}
+
+impl LocalIndex {
+    pub fn crate_(&self, name: &str) -> Option<Crate> {
+        match self {
+            LocalIndex::Sparse { index, .. } => index.crate_from_cache(name).ok(),
+            LocalIndex::Git(index) => index.crate_(name),
+        }
+    }
+}
+
+/// The names of the crates in a sparse index's cache, which are its file names.
+fn cached_crate_names(dir: &Path, names: &mut Vec<String>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            cached_crate_names(&entry.path(), names)?;
+        } else if let Some(name) = entry.file_name().to_str() {
+            names.push(name.to_owned());
+        }
+    }
+    Ok(())
+}
+
+/// The most recently used directory in the registry index directory whose name starts with
+/// `prefix`, since an older cargo may have left one with a different hash.
+fn find_index_dir(index_dir: &Path, prefix: &str, marker: &str) -> Result<Option<PathBuf>> {
+    if !index_dir.is_dir() {
+        return Ok(None);
+    }
+    let mut found = vec![];
+    for entry in fs::read_dir(index_dir)? {
+        let path = entry?.path();
+        let matches = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(prefix));
+        if matches && path.join(marker).exists() {
+            let modified = fs::metadata(path.join(marker))?.modified()?;
+            found.push((modified, path));
+        }
+    }
+    Ok(found.into_iter().max().map(|(_, path)| path))
+}
+
+/// Opens the index cargo has already downloaded, without fetching it if it isn't there. The sparse
+/// index's cache is preferred over a git checkout, as current versions of cargo use it.
+pub fn get_local_index() -> Result<LocalIndex> {
+    let index_dir = get_cargo_home().join("registry").join("index");
+    if let Some(path) = find_index_dir(&index_dir, SPARSE_INDEX_PREFIX, ".cache")? {
+        let index = SparseIndex::at_path(path.clone(), CRATES_IO_HTTP_INDEX.to_owned());
+        return Ok(LocalIndex::Sparse { index, path });
+    }
+    if let Some(path) = find_index_dir(&index_dir, GIT_INDEX_PREFIX, ".git")? {
+        return Ok(LocalIndex::Git(Index::with_path(path, INDEX_GIT_URL)?));
+    }
+    Err(format!(
+        "Could not find a local copy of the crates.io index in '{}'.",
+        index_dir.display()
+    )
+    .into())
+}
+
+pub struct SearchResult {
+    pub name: String,
+    pub latest: Version,
+    pub yanked: bool,
+}
+
+pub fn normalize_crate_name(name: &str) -> String {
+    name.to_lowercase().replace('-', "_")
+}
+
+/// How many edits apart two crate names can be while still looking like a typo of each other.
+pub fn max_typo_distance(name: &str) -> usize {
+    (name.chars().count() / 4).max(1)
+}
+
+/// How closely a crate name matches a normalized search query, lower being closer. Exact
+/// matches come first, then prefixes, then substrings, then names within typo distance.
+fn search_rank(query: &str, name: &str) -> Option<(u8, usize)> {
+    let name = normalize_crate_name(name);
+    if name == query {
+        Some((0, 0))
+    } else if name.starts_with(query) {
+        Some((1, name.len() - query.len()))
+    } else if name.contains(query) {
+        Some((2, name.len() - query.len()))
+    } else {
+        let distance = edit_distance(query, &name);
+        if distance <= max_typo_distance(query) {
+            Some((3, distance))
+        } else {
+            None
+        }
+    }
+}
+
+pub fn search_crates(index: &LocalIndex, query: &str) -> Result<Vec<SearchResult>> {
+    let query = normalize_crate_name(query);
+    let mut matches: Vec<_> = match index {
+        LocalIndex::Sparse { index, path } => {
+            let mut names = vec![];
+            cached_crate_names(&path.join(".cache"), &mut names)?;
+            names
+                .par_iter()
+                .filter_map(|name| {
+                    let rank = search_rank(&query, name)?;
+                    Some((rank, index.crate_from_cache(name).ok()?))
+                })
+                .collect()
+        }
+        LocalIndex::Git(index) => index
+            .crates_parallel()
+            .filter_map(|crte| {
+                let crte = crte.ok()?;
+                search_rank(&query, crte.name()).map(|rank| (rank, crte))
+            })
+            .collect(),
+    };
+    matches.sort_by(|(rank_a, crate_a), (rank_b, crate_b)| {
+        rank_a.cmp(rank_b).then(crate_a.name().cmp(crate_b.name()))
+    });
+
+    let mut results = vec![];
+    for (_, crte) in matches {
+        let indexed = IndexedCrate::new(crte)?;
+        // Crates with only prereleases show their highest prerelease instead.
+        let i = indexed.latest_index().unwrap_or_else(|| {
+            (0..indexed.versions.len())
+                .max_by_key(|&i| &indexed.versions[i])
+                .unwrap_or(0)
+        });
+        results.push(SearchResult {
+            name: indexed.name().to_owned(),
+            latest: indexed.versions[i].clone(),
+            yanked: indexed.crte.versions()[i].is_yanked(),
+        });
+    }
+    Ok(results)
+}
+
+/// The names of every crate in a git index, read from the file names in its tree so no crate has
+/// to be parsed. The index is read at the commit `crates_index` reads it at.
+fn index_crate_names(index: &Index) -> Result<Vec<String>> {
+    let repo = Repository::open(index.path())?;
+    let head = repo
+        .refname_to_id("FETCH_HEAD")
+        .or_else(|_| repo.refname_to_id("HEAD"))?;
+    let mut names = vec![];
+    repo.find_commit(head)?
+        .tree()?
+        .walk(TreeWalkMode::PreOrder, |root, entry| {
+            // Crates are in the short directories; the rest is `config.json` and dotfiles.
+            if root.is_empty() && entry.name_bytes().len() > 2 {
+                return TreeWalkResult::Skip;
+            }
+            if entry.kind() == Some(ObjectType::Blob) {
+                if let Some(name) = entry.name() {
+                    names.push(name.to_owned());
+                }
+            }
+            TreeWalkResult::Ok
+        })?;
+    Ok(names)
+}
+
+/// The names within typo distance of `name`, closest first. Names too much longer or shorter to be
+/// in reach are skipped before working out the distance.
+fn typo_neighbours<'a>(name: &str, names: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    let normalized = normalize_crate_name(name);
+    let length = normalized.chars().count();
+    let max_distance = max_typo_distance(&normalized);
+    let mut close: Vec<_> = names
+        .into_iter()
+        .filter(|other| other.chars().count().abs_diff(length) <= max_distance)
+        .filter_map(|other| {
+            let distance = edit_distance(&normalized, &normalize_crate_name(other));
+            (distance <= max_distance).then_some((distance, other))
+        })
+        .collect();
+    close.sort_unstable();
+    close.dedup();
+    close.into_iter().map(|(_, other)| other).collect()
+}
+
+/// Index crates whose names are within typo distance of `name`, closest first.
+pub fn similar_crate_names(index: &Index, name: &str) -> Result<Vec<String>> {
+    let names = index_crate_names(index)?;
+    let close = typo_neighbours(name, names.iter().map(String::as_str));
+    Ok(load_crates(index, &close)?
+        .iter()
+        .map(|crte| crte.name().to_owned())
+        .collect())
+}
+
+/// How many times as many published versions a crate needs than one whose name is a typo of it to
+/// count as much more popular. The index records nothing closer to popularity than that.
+const POPULARITY_RATIO: usize = 5;
+
+fn much_more_popular(known_crate: &IndexedCrate, crte: &IndexedCrate) -> bool {
+    known_crate.versions.len() >= crte.versions.len() * POPULARITY_RATIO
+}
+
+/// Crates from `known` whose names are a typo away from `crte`'s and which are much more popular
+/// than it, going by `POPULARITY_RATIO`.
+pub fn typosquat_targets(
+    index: &Index,
+    crte: &IndexedCrate,
+    known: &[&str],
+) -> Result<Vec<String>> {
+    let normalized = normalize_crate_name(crte.name());
+    let mut close: Vec<&str> = typo_neighbours(crte.name(), known.iter().copied())
+        .into_iter()
+        .filter(|name| normalize_crate_name(name) != normalized)
+        .collect();
+    close.sort_unstable();
+
+    Ok(load_crates(index, &close)?
+        .into_iter()
+        .filter(|known_crate| much_more_popular(known_crate, crte))
+        .map(|known_crate| known_crate.name().to_owned())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{git_index, indexed_crate, scratch_dir};
+
+    #[test]
+    fn optional_dependencies_are_implicit_features() {
+        let crte = indexed_crate(&[("1.0.0", false, r#"{"fast":[]}"#, &["serde"])]);
+        let features = vec!["fast".to_owned(), "serde".to_owned(), "slow".to_owned()];
+        assert_eq!(missing_features(&features, &crte, 0), vec!["slow"]);
+    }
+
+    #[test]
+    fn dep_syntax_hides_the_implicit_feature() {
+        let crte = indexed_crate(&[("1.0.0", false, r#"{"serde":["dep:serde"]}"#, &["serde"])]);
+        assert!(missing_features(&["serde".to_owned()], &crte, 0).is_empty());
+
+        let crte = indexed_crate(&[("1.0.0", false, r#"{"json":["dep:serde"]}"#, &["serde"])]);
+        assert_eq!(
+            missing_features(&["serde".to_owned()], &crte, 0),
+            vec!["serde"]
+        );
+    }
+
+    fn out_of_date(requirement: &str, strict: bool, versions: &[&str]) -> bool {
+        let versions: Vec<_> = versions
+            .iter()
+            .map(|version| (*version, false, "{}", &[][..]))
+            .collect();
+        let dependency =
+            crate::cargoreader::parse_dependency_value("demo", requirement.into()).unwrap();
+        is_out_of_date(strict, false, &dependency, &indexed_crate(&versions)).unwrap()
+    }
+
+    #[test]
+    fn older_requirements_are_out_of_date() {
+        assert!(out_of_date("1.8", false, &["1.8.0", "1.9.0", "2.0.0"]));
+        assert!(!out_of_date("1.8", false, &["1.8.0", "1.9.0"]));
+        assert!(out_of_date("1.8", true, &["1.8.0", "1.9.0"]));
+        assert!(!out_of_date("1.9.0", true, &["1.8.0", "1.9.0"]));
+    }
+
+    #[test]
+    fn prereleases_past_the_latest_are_up_to_date() {
+        let versions = ["1.9.0", "2.0.0-rc.1"];
+        assert!(!out_of_date("2.0.0-rc.1", false, &versions));
+        assert!(!out_of_date("2.0.0-rc.1", true, &versions));
+        assert!(out_of_date("1.9.0-rc.1", true, &versions));
+    }
+
+    fn dependency(value: &str) -> Dependency {
+        let value: toml::Value = format!("demo = {}", value).parse().unwrap();
+        crate::cargoreader::parse_dependency_value("demo", value["demo"].clone()).unwrap()
+    }
+
+    fn pick_problem(value: &str, version: &str, pin: Option<&str>) -> Option<String> {
+        let crte = indexed_crate(&[
+            ("1.0.0", false, r#"{"fast":[]}"#, &[]),
+            ("1.1.0", false, r#"{"fast":[]}"#, &[]),
+            ("1.2.0", true, r#"{"fast":[]}"#, &[]),
+            ("2.0.0", false, "{}", &[]),
+        ]);
+        let pin = pin.map(|pin| pin.parse().unwrap());
+        picked_version_problem(
+            &dependency(value),
+            &crte,
+            &version.parse().unwrap(),
+            pin.as_ref(),
+        )
+    }
+
+    #[test]
+    fn picked_versions_must_be_published_updates() {
+        assert_eq!(pick_problem("\"1.0\"", "1.1.0", None), None);
+        assert_eq!(pick_problem("\"1.0\"", "2.0.0", None), None);
+        assert!(pick_problem("\"1.0\"", "1.3.0", None).is_some());
+        assert!(pick_problem("\"1.0\"", "1.2.0", None).is_some());
+        assert!(pick_problem("\"1.1\"", "1.1.0", None).is_some());
+        assert!(pick_problem("\"1.1\"", "1.0.0", None).is_some());
+        assert!(pick_problem("\"*\"", "1.0.0", None).is_some());
+        assert_eq!(pick_problem("\"*\"", "1.1.0", None), None);
+    }
+
+    #[test]
+    fn picked_versions_must_keep_pins_and_features() {
+        assert_eq!(pick_problem("\"1.0\"", "1.1.0", Some("^1")), None);
+        assert_eq!(
+            pick_problem("\"1.0\"", "2.0.0", Some("^1")),
+            Some("demo is pinned to (1) by the project's carp config.".to_owned())
+        );
+        let fast = "{ version = \"1.0\", features = [\"fast\"] }";
+        assert_eq!(pick_problem(fast, "1.1.0", None), None);
+        assert_eq!(
+            pick_problem(fast, "2.0.0", None),
+            Some("(2.0.0) doesn't have the feature(s) 'fast'.".to_owned())
+        );
+    }
+
+    fn matches(requirement: &str, floor: Option<&str>) -> RequirementMatches {
+        let crte = indexed_crate(&[
+            ("0.9.0", false, "{}", &[]),
+            ("1.0.0", false, "{}", &[]),
+            ("1.2.0", false, "{}", &[]),
+            ("1.3.0", true, "{}", &[]),
+            ("2.0.0", false, "{}", &[]),
+        ]);
+        let floor = floor.map(|floor| floor.parse().unwrap());
+        requirement_matches(&crte, &requirement.parse().unwrap(), floor.as_ref())
+    }
+
+    #[test]
+    fn matches_are_newest_first_with_their_neighbours() {
+        let found = matches("^1", Some("1.0.0"));
+        assert_eq!(found.matching, vec![3, 2, 1]);
+        assert_eq!(found.selected, Some(2));
+        assert_eq!(found.below, Some(0));
+        assert_eq!(found.above, Some(4));
+    }
+
+    #[test]
+    fn yanked_matches_are_never_selected() {
+        let found = matches(">=1.3.0, <2", Some("1.3.0"));
+        assert_eq!(found.matching, vec![3]);
+        assert_eq!(found.selected, None);
+        assert_eq!(found.below, Some(2));
+        assert_eq!(found.above, Some(4));
+    }
+
+    #[test]
+    fn neighbours_without_matches_surround_the_floor() {
+        let found = matches("=1.1.0", Some("1.1.0"));
+        assert!(found.matching.is_empty());
+        assert_eq!(found.selected, None);
+        assert_eq!(found.below, Some(1));
+        assert_eq!(found.above, Some(2));
+
+        let found = matches("^3", Some("3.0.0"));
+        assert_eq!((found.below, found.above), (Some(4), None));
+
+        let found = matches("^3", None);
+        assert_eq!((found.below, found.above), (None, None));
+    }
+
+    #[test]
+    fn typo_neighbours_are_closest_first() {
+        let names = ["serde", "serde_json", "sered", "serd", "Serde-", "tokio"];
+        assert_eq!(
+            typo_neighbours("serde", names),
+            vec!["serde", "Serde-", "serd", "sered"]
+        );
+        assert_eq!(typo_neighbours("serde-jsno", names), vec!["serde_json"]);
+        assert!(typo_neighbours("rand", names).is_empty());
+    }
+
+    #[test]
+    fn similar_names_come_from_the_whole_index() {
+        let dir = scratch_dir("similar-names");
+        let index = git_index(
+            &dir,
+            &[("a", 1), ("ab", 1), ("rand", 3), ("Rang", 1), ("serde", 2)],
+        );
+        let mut names = index_crate_names(&index).unwrap();
+        names.sort();
+        assert_eq!(names, vec!["a", "ab", "rand", "rang", "serde"]);
+        assert_eq!(
+            similar_crate_names(&index, "ranf").unwrap(),
+            vec!["rand", "Rang"]
+        );
+        assert!(similar_crate_names(&index, "tokio").unwrap().is_empty());
+    }
+
+    #[test]
+    fn typosquats_copy_much_more_popular_names() {
+        let dir = scratch_dir("typosquats");
+        let index = git_index(
+            &dir,
+            &[("serde", 10), ("rand", 4), ("sered", 1), ("ranf", 1)],
+        );
+        let typosquat_of = |name: &str| {
+            let crte = load_crate(&index, name).unwrap();
+            typosquat_targets(&index, &crte, &["serde", "rand", "tokio"]).unwrap()
+        };
+        assert_eq!(typosquat_of("sered"), vec!["serde"]);
+        assert!(typosquat_of("ranf").is_empty());
+        assert!(typosquat_of("serde").is_empty());
+    }
+
+    #[test]
+    fn search_ranks_exact_then_prefix_then_substring_then_typos() {
+        let rank = |name: &str| search_rank("serde", name);
+        assert_eq!(rank("Serde"), Some((0, 0)));
+        assert_eq!(rank("serde_json"), Some((1, 5)));
+        assert_eq!(rank("serde-yaml"), Some((1, 5)));
+        assert_eq!(rank("miniserde"), Some((2, 4)));
+        assert_eq!(rank("sered"), Some((3, 1)));
+        assert_eq!(rank("surde"), Some((3, 1)));
+        assert_eq!(rank("tokio"), None);
+        assert!(rank("serde") < rank("serde_json"));
+        assert!(rank("serde_json") < rank("miniserde"));
+        assert!(rank("miniserde") < rank("surde"));
+    }
+
+    #[test]
+    fn search_results_are_ranked_then_named() {
+        let dir = scratch_dir("search");
+        let index = LocalIndex::Git(git_index(
+            &dir,
+            &[
+                ("serde_json", 2),
+                ("miniserde", 1),
+                ("serde", 3),
+                ("serde-yaml", 1),
+                ("surde", 1),
+                ("tokio", 1),
+            ],
+        ));
+        let results = search_crates(&index, "Serde").unwrap();
+        let names: Vec<_> = results.iter().map(|result| result.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["serde", "serde-yaml", "serde_json", "miniserde", "surde"]
+        );
+        assert_eq!(results[0].latest, Version::new(0, 1, 2));
+        assert!(!results[0].yanked);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::utils::Result;
-use semver::{Op, Version, VersionReq};
+use semver::{Comparator, Op, Prerelease, Version, VersionReq};
+use std::ops::Bound;
 use toml::Value;
 
 #[derive(Debug, Clone)]
@@ -16,25 +17,150 @@ pub struct Dependency {
     pub raw_toml_value: RawToml,
 }
 
-pub fn get_version_from_version_req(version_req: &VersionReq) -> Option<Version> {
-    let mut result = None;
-    for comparator in &version_req.comparators {
-        match comparator.op {
-            Op::Exact | Op::Tilde | Op::Caret => {
-                if let (Some(minor), Some(patch)) = (comparator.minor, comparator.patch) {
-                    result = Some(Version::new(comparator.major, minor, patch));
-                    break;
-                }
+impl Dependency {
+    fn table_value(&self, key: &str) -> Option<&Value> {
+        match &self.raw_toml_value {
+            RawToml::String(_) => None,
+            RawToml::Table(table) => table.get(key),
+        }
+    }
+
+    /// The name of the crate on the registry, which differs from `name` when it's renamed with
+    /// `package = "..."`.
+    pub fn crate_name(&self) -> &str {
+        self.table_value("package")
+            .and_then(Value::as_str)
+            .unwrap_or(&self.name)
+    }
+
+    pub fn features(&self) -> Vec<String> {
+        self.table_value("features")
+            .and_then(Value::as_array)
+            .map(|features| {
+                features
+                    .iter()
+                    .filter_map(|feature| feature.as_str().map(str::to_owned))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn default_features(&self) -> bool {
+        self.table_value("default-features")
+            .or_else(|| self.table_value("default_features"))
+            .and_then(Value::as_bool)
+            .unwrap_or(true)
+    }
+
+    /// The repository of a git dependency.
+    pub fn git(&self) -> Option<&str> {
+        self.table_value("git").and_then(Value::as_str)
+    }
+
+    /// The name of the registry a dependency comes from, when it isn't crates.io.
+    pub fn registry(&self) -> Option<&str> {
+        self.table_value("registry").and_then(Value::as_str)
+    }
+
+    pub fn path(&self) -> Option<&str> {
+        self.table_value("path").and_then(Value::as_str)
+    }
+
+    pub fn is_optional(&self) -> bool {
+        self.table_value("optional")
+            .and_then(Value::as_bool)
+            .unwrap_or(false)
+    }
+}
+
+/// The lowest version a comparator allows, with missing minor and patch numbers taken as 0, e.g.
+/// `1.2.3-beta.2` for `^1.2.3-beta.2`, `1.2.0` for `~1.2` and `1.0.0` for `1.*`. A `>` comparator
+/// allows the next release after the one it names: `>1.2.3` is `1.2.4`, `>1.2` is `1.3.0` and
+/// `>1.2.3-beta` is `1.2.3-beta.0`, the first prerelease sorting after `beta`. `<` and `<=` only
+/// bound versions from above, so they have none.
+fn comparator_floor(comparator: &Comparator) -> Option<Version> {
+    let minor = comparator.minor.unwrap_or(0);
+    let patch = comparator.patch.unwrap_or(0);
+    match comparator.op {
+        Op::Exact | Op::Tilde | Op::Caret | Op::GreaterEq | Op::Wildcard => Some(Version {
+            pre: comparator.pre.clone(),
+            ..Version::new(comparator.major, minor, patch)
+        }),
+        Op::Greater => Some(match (comparator.minor, comparator.patch) {
+            (None, _) => Version::new(comparator.major + 1, 0, 0),
+            (Some(minor), None) => Version::new(comparator.major, minor + 1, 0),
+            (Some(minor), Some(patch)) if comparator.pre.is_empty() => {
+                Version::new(comparator.major, minor, patch + 1)
             }
-            _ => (),
+            (Some(minor), Some(patch)) => Version {
+                pre: Prerelease::new(&format!("{}.0", comparator.pre)).unwrap(),
+                ..Version::new(comparator.major, minor, patch)
+            },
+        }),
+        _ => None,
+    }
+}
+
+/// The version a comparator allows everything below, where it has one. Ceilings that end a series
+/// exclude its prereleases too, so `^1.2` stops before `2.0.0-0` and `~1.2` before `1.3.0-0`.
+fn comparator_ceiling(comparator: &Comparator) -> Bound<Version> {
+    let next_series = |major: u64, minor: u64, patch: u64| {
+        Bound::Excluded(Version {
+            pre: Prerelease::new("0").unwrap(),
+            ..Version::new(major, minor, patch)
+        })
+    };
+    let exact = || Version {
+        pre: comparator.pre.clone(),
+        ..Version::new(
+            comparator.major,
+            comparator.minor.unwrap_or(0),
+            comparator.patch.unwrap_or(0),
+        )
+    };
+    match (comparator.op, comparator.minor, comparator.patch) {
+        (Op::Greater | Op::GreaterEq, _, _) => Bound::Unbounded,
+        (Op::Less, _, _) => Bound::Excluded(exact()),
+        (Op::Exact | Op::LessEq, Some(_), Some(_)) => Bound::Included(exact()),
+        (Op::Caret, Some(minor), Some(patch)) if comparator.major == 0 && minor == 0 => {
+            next_series(0, 0, patch + 1)
         }
+        (Op::Caret, Some(minor), _) if comparator.major == 0 => next_series(0, minor + 1, 0),
+        (Op::Caret, _, _) | (_, None, _) => next_series(comparator.major + 1, 0, 0),
+        (_, Some(minor), _) => next_series(comparator.major, minor + 1, 0),
     }
-    result
+}
+
+/// Whether some version satisfies both requirements, e.g. `^0.1.40` and a ban on `<0.1.45`.
+pub fn requirements_overlap(a: &VersionReq, b: &VersionReq) -> bool {
+    let comparators = || a.comparators.iter().chain(&b.comparators);
+    let floor = comparators()
+        .filter_map(comparator_floor)
+        .max()
+        .unwrap_or_else(|| Version::new(0, 0, 0));
+    comparators()
+        .map(comparator_ceiling)
+        .all(|ceiling| match ceiling {
+            Bound::Included(ceiling) => floor <= ceiling,
+            Bound::Excluded(ceiling) => floor < ceiling,
+            Bound::Unbounded => true,
+        })
+}
+
+/// The version a requirement is pinned at for strict comparison: the lowest version it allows,
+/// which is the highest floor among its comparators. `*` and requirements with only upper bounds,
+/// like `<2`, have none.
+pub fn get_version_from_version_req(version_req: &VersionReq) -> Option<Version> {
+    version_req
+        .comparators
+        .iter()
+        .filter_map(comparator_floor)
+        .max()
 }
 
 pub fn get_version_req_from_version(version: &Version) -> VersionReq {
     VersionReq {
-        comparators: vec![semver::Comparator {
+        comparators: vec![Comparator {
             op: Op::Caret,
             major: version.major,
             minor: Some(version.minor),
@@ -69,3 +195,94 @@ pub fn transform_dependency_version(ver_str: &str, dependency: Dependency) -> Re
         raw_toml_value: transformed_raw_toml,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlap(a: &str, b: &str) -> bool {
+        requirements_overlap(&a.parse().unwrap(), &b.parse().unwrap())
+    }
+
+    #[test]
+    fn requirements_overlap_past_their_floors() {
+        assert!(overlap("0.1.40", "<0.1.45"));
+        assert!(overlap("^1.2", ">=1.9"));
+        assert!(overlap("*", "=0.3.1"));
+        assert!(overlap("~1.2.3", "1.2.9"));
+        assert!(overlap(">1.2, <1.4", "=1.3"));
+        assert!(overlap(">1.2.3-beta", "<1.2.3"));
+    }
+
+    #[test]
+    fn disjoint_requirements_do_not_overlap() {
+        assert!(!overlap("0.1.45", "<0.1.45"));
+        assert!(!overlap("0.2", "0.1"));
+        assert!(!overlap("0.0.3", ">=0.0.4"));
+        assert!(!overlap("~1.2", ">=1.3"));
+        assert!(!overlap("1.2.*", "=1.3.0"));
+        assert!(!overlap("<=1.2.3", ">1.2.3"));
+        assert!(!overlap("^1", "=2.0.0-alpha.1"));
+        assert!(!overlap(">1.2.3-beta", "=1.2.3-beta"));
+    }
+
+    #[test]
+    fn exact_versions_overlap_only_themselves() {
+        assert!(overlap("=1.2.3", "<=1.2.3"));
+        assert!(!overlap("=1.2.3", "<1.2.3"));
+        assert!(overlap("=1.2", "=1.2.7"));
+    }
+
+    fn floor(requirement: &str) -> Option<Version> {
+        let version_req: VersionReq = requirement.parse().unwrap();
+        comparator_floor(&version_req.comparators[0])
+    }
+
+    fn version(version: &str) -> Option<Version> {
+        Some(version.parse().unwrap())
+    }
+
+    #[test]
+    fn floors_of_lower_bounds() {
+        assert_eq!(floor("=1.2.3"), version("1.2.3"));
+        assert_eq!(floor("^1.2.3"), version("1.2.3"));
+        assert_eq!(floor("~1.2.3"), version("1.2.3"));
+        assert_eq!(floor(">=1.2.3"), version("1.2.3"));
+        assert_eq!(floor("1.2.*"), version("1.2.0"));
+        assert_eq!(floor(">1.2.3"), version("1.2.4"));
+    }
+
+    #[test]
+    fn floors_of_partial_versions() {
+        assert_eq!(floor("1"), version("1.0.0"));
+        assert_eq!(floor("~1.2"), version("1.2.0"));
+        assert_eq!(floor("=0.3"), version("0.3.0"));
+        assert_eq!(floor("1.*"), version("1.0.0"));
+        assert_eq!(floor(">1"), version("2.0.0"));
+        assert_eq!(floor(">1.2"), version("1.3.0"));
+    }
+
+    #[test]
+    fn floors_of_prereleases() {
+        assert_eq!(floor("^1.2.3-beta.2"), version("1.2.3-beta.2"));
+        assert_eq!(floor(">=2.0.0-rc.1"), version("2.0.0-rc.1"));
+        assert_eq!(floor(">1.2.3-beta"), version("1.2.3-beta.0"));
+        assert_eq!(floor(">1.2.3-rc.1"), version("1.2.3-rc.1.0"));
+    }
+
+    #[test]
+    fn upper_bounds_have_no_floor() {
+        assert_eq!(floor("<1.2.3"), None);
+        assert_eq!(floor("<=1.2"), None);
+    }
+
+    #[test]
+    fn a_requirement_is_pinned_at_its_highest_floor() {
+        let pinned =
+            |requirement: &str| get_version_from_version_req(&requirement.parse().unwrap());
+        assert_eq!(pinned(">=1.2, <1.5"), version("1.2.0"));
+        assert_eq!(pinned(">1.2.3, >=1.2.5"), version("1.2.5"));
+        assert_eq!(pinned("*"), None);
+        assert_eq!(pinned("<2"), None);
+    }
+}
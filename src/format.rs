@@ -0,0 +1,336 @@
+use crate::common::{version_req_str, DEPENDENCY_SECTIONS};
+use crate::config::TableStyle;
+use crate::utils::{Join, Result};
+use semver::VersionReq;
+use std::cmp::Ordering;
+use std::mem;
+use toml::value::Table;
+use toml::Value;
+use toml_edit::{Decor, Document, Item, Key, RawString};
+
+/// Spells a requirement the way carp writes them, e.g. `^1.2` as `1.2`. Anything that doesn't
+/// parse is left for cargo to complain about.
+fn normalize_requirement(requirement: &str) -> String {
+    match requirement.parse::<VersionReq>() {
+        Ok(version_req) => version_req_str(&version_req),
+        Err(_) => requirement.to_owned(),
+    }
+}
+
+/// A key as it's written in TOML, quoted unless it's a bare key.
+pub fn key_str(key: &str) -> String {
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if bare {
+        key.to_owned()
+    } else {
+        Value::String(key.to_owned()).to_string()
+    }
+}
+
+/// A table's entries with `version` first, since that's what's looked for in a dependency.
+fn version_first(table: &Table) -> Vec<(&String, &Value)> {
+    let mut entries: Vec<_> = table.iter().collect();
+    entries.sort_by_key(|(key, _)| key.as_str() != "version");
+    entries
+}
+
+/// A value written on one line, with tables as inline tables.
+pub fn inline_value(value: &Value) -> String {
+    match value {
+        Value::Table(table) if table.is_empty() => "{}".to_owned(),
+        Value::Table(table) => format!(
+            "{{ {} }}",
+            version_first(table)
+                .into_iter()
+                .map(|(key, value)| format!("{} = {}", key_str(key), inline_value(value)))
+                .join(", ")
+        ),
+        Value::Array(values) => format!("[{}]", values.iter().map(inline_value).join(", ")),
+        other => other.to_string(),
+    }
+}
+
+/// The text of a decor's prefix or suffix, or nothing if it has none.
+fn decor_text(raw: Option<&RawString>) -> &str {
+    raw.and_then(RawString::as_str).unwrap_or("")
+}
+
+/// Whether a table, or anything in it, has a comment, which an inline table has no room for.
+fn table_has_comments(table: &toml_edit::Table) -> bool {
+    table.iter().any(|(key, item)| {
+        let key_decor = table.key_decor(key);
+        let decors = [key_decor, item.as_value().map(toml_edit::Value::decor)];
+        decors.into_iter().flatten().any(|decor| {
+            decor_text(decor.prefix()).contains('#') || decor_text(decor.suffix()).contains('#')
+        })
+    })
+}
+
+/// Normalizes a requirement written as a string, keeping the whitespace and comments around it.
+fn normalize_value(value: &mut toml_edit::Value) {
+    let normalized = match value.as_str() {
+        Some(requirement) if normalize_requirement(requirement) != requirement => {
+            normalize_requirement(requirement)
+        }
+        _ => return,
+    };
+    let decor = value.decor().clone();
+    *value = toml_edit::Value::from(normalized);
+    *value.decor_mut() = decor;
+}
+
+/// Why `version` sorts first among a dependency's keys.
+fn version_first_order(a: &Key, b: &Key) -> Ordering {
+    (a.get() != "version").cmp(&(b.get() != "version"))
+}
+
+/// A dependency's entry with its requirement normalized, its `version` key first, a table with
+/// nothing but a version turned into the plain string form, and other tables written in `style`.
+/// `key_prefix` is what's written above the entry's key, which moves onto a table's header and
+/// back when the entry changes between the two.
+fn format_entry(item: Item, key_prefix: &mut String, style: TableStyle) -> Item {
+    match item {
+        Item::Value(toml_edit::Value::String(_)) => {
+            let mut value = item.into_value().unwrap();
+            normalize_value(&mut value);
+            Item::Value(value)
+        }
+        Item::Value(toml_edit::Value::InlineTable(mut table)) => {
+            if let Some(version) = table.get_mut("version") {
+                normalize_value(version);
+            }
+            if table
+                .iter()
+                .position(|(key, _)| key == "version")
+                .unwrap_or(0)
+                > 0
+            {
+                table.sort_values_by(|a, _, b, _| version_first_order(a, b));
+                table.fmt();
+            }
+            if table.len() == 1 && table.contains_key("version") {
+                let decor = table.decor().clone();
+                let mut version = table.remove("version").unwrap();
+                *version.decor_mut() = decor;
+                return Item::Value(version);
+            }
+            if style == TableStyle::Inline {
+                return Item::Value(toml_edit::Value::InlineTable(table));
+            }
+            let suffix = decor_text(table.decor().suffix()).to_owned();
+            let mut expanded = table.into_table();
+            expanded.decor_mut().set_prefix(format!("\n{}", key_prefix));
+            expanded.decor_mut().set_suffix(suffix);
+            key_prefix.clear();
+            Item::Table(expanded)
+        }
+        Item::Table(mut table) if !table.is_dotted() => {
+            if let Some(version) = table.get_mut("version").and_then(Item::as_value_mut) {
+                normalize_value(version);
+            }
+            table.sort_values_by(|a, _, b, _| version_first_order(a, b));
+            let only_version = table.len() == 1 && table.get("version").is_some_and(Item::is_value);
+            if table_has_comments(&table) || !(only_version || style == TableStyle::Inline) {
+                return Item::Table(table);
+            }
+            *key_prefix = decor_text(table.decor().prefix())
+                .trim_start_matches(['\r', '\n'])
+                .to_owned();
+            let suffix = decor_text(table.decor().suffix()).to_owned();
+            let mut value = if only_version {
+                table.remove("version").unwrap().into_value().unwrap()
+            } else {
+                toml_edit::Value::InlineTable(table.into_inline_table())
+            };
+            value.decor_mut().set_prefix(" ");
+            value.decor_mut().set_suffix(suffix);
+            Item::Value(value)
+        }
+        other => other,
+    }
+}
+
+/// How much of a table's prefix is the blank lines above its first comment.
+fn blank_lines_len(prefix: &str) -> usize {
+    let whitespace = prefix.len() - prefix.trim_start().len();
+    prefix[..whitespace]
+        .rfind('\n')
+        .map_or(0, |newline| newline + 1)
+}
+
+/// Formats the entries of one dependency table and sorts them, with any `[section.name]` tables
+/// written together, in order, where the first of them was.
+fn format_dependency_table(table: &mut toml_edit::Table, style: TableStyle) {
+    let first_position = table
+        .iter()
+        .filter_map(|(_, item)| item.as_table().filter(|subtable| !subtable.is_dotted()))
+        .filter_map(toml_edit::Table::position)
+        .min()
+        .or(table.position());
+    let names: Vec<String> = table.iter().map(|(name, _)| name.to_owned()).collect();
+    for name in &names {
+        let mut key_prefix = decor_text(table.key_decor(name).and_then(Decor::prefix)).to_owned();
+        let slot = table.get_mut(name).unwrap();
+        let was_table = slot.is_table();
+        *slot = format_entry(mem::replace(slot, Item::None), &mut key_prefix, style);
+        let is_table = slot.is_table();
+        if let Some(decor) = table.key_decor_mut(name) {
+            if is_table {
+                *decor = Decor::default();
+            } else {
+                if was_table {
+                    decor.set_suffix(" ");
+                }
+                decor.set_prefix(key_prefix);
+            }
+        }
+    }
+    table.sort_values();
+
+    // Blank lines above a table's header stay where they were rather than moving with the table.
+    let mut subtables: Vec<&mut toml_edit::Table> = table
+        .iter_mut()
+        .filter_map(|(_, item)| item.as_table_mut())
+        .filter(|subtable| !subtable.is_dotted())
+        .collect();
+    let mut gaps: Vec<(Option<usize>, String)> = subtables
+        .iter()
+        .map(|subtable| {
+            let prefix = decor_text(subtable.decor().prefix());
+            (
+                subtable.position(),
+                prefix[..blank_lines_len(prefix)].to_owned(),
+            )
+        })
+        .collect();
+    gaps.sort_by_key(|(position, _)| position.unwrap_or(usize::MAX));
+    for (subtable, (_, gap)) in subtables.iter_mut().zip(gaps) {
+        let prefix = decor_text(subtable.decor().prefix());
+        let comments = prefix[blank_lines_len(prefix)..].to_owned();
+        subtable.decor_mut().set_prefix(gap + &comments);
+        if let Some(position) = first_position {
+            subtable.set_position(position);
+        }
+    }
+
+    if table.iter().any(|(_, item)| item.is_value()) {
+        table.set_implicit(false);
+        if table.position().is_none() {
+            if let Some(position) = first_position {
+                table.set_position(position);
+            }
+        }
+    }
+}
+
+/// Formats a manifest's text: the entries of every dependency table, including the ones under
+/// `[target]`, are sorted, with their requirements normalized and their tables written in inline or
+/// expanded style. Everything else, comments and the order of the other tables and keys included,
+/// is kept as it was written.
+pub fn format_manifest_text(text: &str, style: TableStyle) -> Result<String> {
+    let mut document: Document = text.parse()?;
+    for section in DEPENDENCY_SECTIONS {
+        if let Some(table) = document.get_mut(section).and_then(Item::as_table_mut) {
+            format_dependency_table(table, style);
+        }
+    }
+    if let Some(targets) = document.get_mut("target").and_then(Item::as_table_mut) {
+        for (_, target) in targets.iter_mut() {
+            for section in DEPENDENCY_SECTIONS {
+                if let Some(table) = target.get_mut(section).and_then(Item::as_table_mut) {
+                    format_dependency_table(table, style);
+                }
+            }
+        }
+    }
+    Ok(document.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(text: &str, style: TableStyle) -> String {
+        format_manifest_text(text, style).unwrap()
+    }
+
+    #[test]
+    fn sorts_and_normalizes_dependencies_in_place() {
+        let manifest = "[package]\nversion = \"0.1.0\"\nname = \"demo\"\n\n\
+            [dependencies]\nserde = { version = \"^1.0\" }\nanyhow = \"^1.0.40\"\n\n\
+            [profile.release]\nlto = true\n";
+        assert_eq!(
+            format(manifest, TableStyle::Inline),
+            "[package]\nversion = \"0.1.0\"\nname = \"demo\"\n\n\
+            [dependencies]\nanyhow = \"1.0.40\"\nserde = \"1.0\"\n\n\
+            [profile.release]\nlto = true\n"
+        );
+    }
+
+    #[test]
+    fn formatted_manifests_are_left_alone() {
+        let manifest = "[dependencies]\nanyhow = \"1.0\"\n\
+            serde = { version = \"1\", features = [\"derive\"] }\n\n\
+            [package]\nname = \"demo\"\n";
+        assert_eq!(format(manifest, TableStyle::Inline), manifest);
+    }
+
+    #[test]
+    fn keeps_comments() {
+        let manifest = "# The demo package.\n[package]\nname = \"demo\" # not final\n\n\
+            [dependencies]\n# Parsing.\nserde = \"^1.0\"\n\
+            # Pinned until the next audit.\nanyhow = \"=1.0.40\" # carp:allow(exact-pin)\n";
+        assert_eq!(
+            format(manifest, TableStyle::Inline),
+            "# The demo package.\n[package]\nname = \"demo\" # not final\n\n\
+            [dependencies]\n# Pinned until the next audit.\n\
+            anyhow = \"=1.0.40\" # carp:allow(exact-pin)\n# Parsing.\nserde = \"1.0\"\n"
+        );
+    }
+
+    #[test]
+    fn writes_tables_in_either_style() {
+        let inline = "[dependencies]\ntokio = { features = [\"full\"], version = \"1\" } # async\n";
+        let sorted = "[dependencies]\ntokio = { version = \"1\", features = [\"full\"] } # async\n";
+        assert_eq!(format(inline, TableStyle::Inline), sorted);
+
+        let expanded = format(inline, TableStyle::Expanded);
+        assert_eq!(
+            expanded,
+            "[dependencies]\n\n[dependencies.tokio] # async\nversion = \"1\"\nfeatures = [\"full\"]\n"
+        );
+        assert_eq!(format(&expanded, TableStyle::Inline), sorted);
+    }
+
+    #[test]
+    fn sorts_expanded_tables_where_the_first_was() {
+        let manifest = "[dependencies.serde]\nversion = \"1\"\nfeatures = [\"derive\"]\n\n\
+            [profile.release]\nlto = true\n\n\
+            [dependencies.anyhow]\nversion = \"1\"\n# Backtraces are slow.\ndefault-features = false\n";
+        assert_eq!(
+            format(manifest, TableStyle::Expanded),
+            "[dependencies.anyhow]\nversion = \"1\"\n# Backtraces are slow.\ndefault-features = false\n\n\
+            [dependencies.serde]\nversion = \"1\"\nfeatures = [\"derive\"]\n\n\
+            [profile.release]\nlto = true\n"
+        );
+        // An inline table has no room for the comment in anyhow's table, so it stays expanded.
+        assert_eq!(
+            format(manifest, TableStyle::Inline),
+            "[dependencies]\nserde = { version = \"1\", features = [\"derive\"] }\n\n\
+            [dependencies.anyhow]\nversion = \"1\"\n# Backtraces are slow.\ndefault-features = false\n\n\
+            [profile.release]\nlto = true\n"
+        );
+    }
+
+    #[test]
+    fn formats_target_dependencies() {
+        let manifest = "[target.'cfg(unix)'.dependencies]\nlibc = \"^0.2\"\nbitflags = \"2\"\n";
+        assert_eq!(
+            format(manifest, TableStyle::Inline),
+            "[target.'cfg(unix)'.dependencies]\nbitflags = \"2\"\nlibc = \"0.2\"\n"
+        );
+    }
+}
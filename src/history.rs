@@ -0,0 +1,297 @@
+use crate::common::{get_cargo_path, get_history_dir, get_lock_path, lock_manifest};
+use crate::utils::{write_file_atomically, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use toml::Value;
+
+/// How many earlier states are kept before the oldest ones are dropped.
+const MAX_ENTRIES: usize = 50;
+
+const MANIFEST_FILE: &str = "cargo.toml";
+const LOCK_FILE: &str = "Cargo.lock";
+const ENTRY_FILE: &str = "entry.toml";
+
+/// The manifest, and lockfile if there was one, as they were before a command changed them.
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub id: usize,
+    /// The command that changed the manifest afterwards, e.g. `update serde`.
+    pub reason: String,
+    /// Seconds since the Unix epoch.
+    pub time: u64,
+    pub dir: PathBuf,
+}
+
+impl HistoryEntry {
+    pub fn manifest_path(&self) -> PathBuf {
+        self.dir.join(MANIFEST_FILE)
+    }
+
+    pub fn lockfile_path(&self) -> PathBuf {
+        self.dir.join(LOCK_FILE)
+    }
+
+    pub fn has_lockfile(&self) -> bool {
+        self.lockfile_path().is_file()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+fn read_entry(dir: PathBuf) -> Result<Option<HistoryEntry>> {
+    let id = match dir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.parse().ok())
+    {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+    let entry_value: Value = fs::read_to_string(dir.join(ENTRY_FILE))?.parse()?;
+    let reason = entry_value
+        .get("reason")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_owned();
+    let time = entry_value
+        .get("time")
+        .and_then(Value::as_integer)
+        .and_then(|time| u64::try_from(time).ok())
+        .unwrap_or(0);
+    Ok(Some(HistoryEntry {
+        id,
+        reason,
+        time,
+        dir,
+    }))
+}
+
+fn entries_in(history_dir: &Path) -> Result<Vec<HistoryEntry>> {
+    if !history_dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut entries = vec![];
+    for dir in fs::read_dir(history_dir)? {
+        let dir = dir?.path();
+        if dir.join(ENTRY_FILE).is_file() {
+            entries.extend(read_entry(dir)?);
+        }
+    }
+    entries.sort_by_key(|entry| entry.id);
+    Ok(entries)
+}
+
+/// Every saved state, oldest first.
+pub fn list_history() -> Result<Vec<HistoryEntry>> {
+    entries_in(&get_history_dir())
+}
+
+fn record_in(
+    history_dir: &Path,
+    manifest_path: &Path,
+    lock_path: &Path,
+    reason: &str,
+) -> Result<HistoryEntry> {
+    let entries = entries_in(history_dir)?;
+    let id = entries.last().map_or(1, |entry| entry.id + 1);
+    let dir = history_dir.join(id.to_string());
+    fs::create_dir_all(&dir)?;
+
+    fs::copy(manifest_path, dir.join(MANIFEST_FILE))?;
+    if lock_path.is_file() {
+        fs::copy(lock_path, dir.join(LOCK_FILE))?;
+    }
+    let time = now();
+    let mut entry_table = toml::map::Map::new();
+    entry_table.insert("reason".to_owned(), Value::from(reason));
+    entry_table.insert("time".to_owned(), Value::from(time as i64));
+    fs::write(
+        dir.join(ENTRY_FILE),
+        toml::ser::to_string(&Value::Table(entry_table))?,
+    )?;
+
+    let excess = (entries.len() + 1).saturating_sub(MAX_ENTRIES);
+    for entry in &entries[..excess] {
+        fs::remove_dir_all(&entry.dir)?;
+    }
+    Ok(HistoryEntry {
+        id,
+        reason: reason.to_owned(),
+        time,
+        dir,
+    })
+}
+
+/// Saves the manifest and lockfile as they are now, before `reason` changes them, dropping the
+/// oldest states past the limit. Returns the entry it saved them in.
+pub fn record(reason: &str) -> Result<HistoryEntry> {
+    record_in(
+        &get_history_dir(),
+        &get_cargo_path(),
+        &get_lock_path(),
+        reason,
+    )
+}
+
+fn restore_to(entry: &HistoryEntry, manifest_path: &Path, lock_path: &Path) -> Result<()> {
+    let manifest = fs::read_to_string(entry.dir.join(MANIFEST_FILE))?;
+    manifest.parse::<Value>()?;
+    write_file_atomically(manifest_path, &manifest)?;
+    if entry.has_lockfile() {
+        write_file_atomically(lock_path, &fs::read_to_string(entry.lockfile_path())?)?;
+    }
+    let history_dir = entry
+        .dir
+        .parent()
+        .ok_or("Could not find the history the entry belongs to.")?;
+    for later in entries_in(history_dir)? {
+        if later.id >= entry.id {
+            fs::remove_dir_all(&later.dir)?;
+        }
+    }
+    Ok(())
+}
+
+/// Puts back the manifest, and lockfile if one was saved, from an entry, and forgets that entry and
+/// every later one.
+pub fn restore(entry: &HistoryEntry) -> Result<()> {
+    let _lock = lock_manifest()?;
+    restore_to(entry, &get_cargo_path(), &get_lock_path())
+}
+
+/// How long ago a time was, roughly, e.g. `5 minutes ago`.
+pub fn time_ago(time: u64) -> String {
+    let seconds = now().saturating_sub(time);
+    let (count, unit) = match seconds {
+        0..=59 => (seconds, "second"),
+        60..=3599 => (seconds / 60, "minute"),
+        3600..=86399 => (seconds / 3600, "hour"),
+        _ => (seconds / 86400, "day"),
+    };
+    format!(
+        "{} {}{} ago",
+        count,
+        unit,
+        if count == 1 { "" } else { "s" }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    struct Package {
+        history_dir: PathBuf,
+        manifest_path: PathBuf,
+        lock_path: PathBuf,
+    }
+
+    impl Package {
+        fn new(name: &str) -> Package {
+            let dir = scratch_dir(name);
+            Package {
+                history_dir: dir.join(".carp").join("history"),
+                manifest_path: dir.join(MANIFEST_FILE),
+                lock_path: dir.join(LOCK_FILE),
+            }
+        }
+
+        fn write(&self, manifest: &str) {
+            fs::write(&self.manifest_path, manifest).unwrap();
+        }
+
+        fn record(&self, reason: &str) -> HistoryEntry {
+            record_in(
+                &self.history_dir,
+                &self.manifest_path,
+                &self.lock_path,
+                reason,
+            )
+            .unwrap()
+        }
+
+        fn ids(&self) -> Vec<usize> {
+            entries_in(&self.history_dir)
+                .unwrap()
+                .iter()
+                .map(|entry| entry.id)
+                .collect()
+        }
+    }
+
+    #[test]
+    fn recording_returns_the_entry_it_saved() {
+        let package = Package::new("history-record");
+        package.write("a = 1\n");
+        let first = package.record("add serde");
+        package.write("a = 2\n");
+        let second = package.record("update serde");
+        assert_eq!((first.id, second.id), (1, 2));
+        assert_eq!(second.reason, "update serde");
+        assert!(!second.has_lockfile());
+        assert_eq!(
+            fs::read_to_string(second.manifest_path()).unwrap(),
+            "a = 2\n"
+        );
+        let listed = entries_in(&package.history_dir).unwrap();
+        assert_eq!(listed[1].reason, "update serde");
+        assert_eq!(listed[1].dir, second.dir);
+    }
+
+    #[test]
+    fn restoring_puts_the_files_back_and_forgets_later_entries() {
+        let package = Package::new("history-restore");
+        package.write("a = 1\n");
+        fs::write(&package.lock_path, "# lock 1\n").unwrap();
+        let first = package.record("add serde");
+        package.write("a = 2\n");
+        fs::write(&package.lock_path, "# lock 2\n").unwrap();
+        package.record("update serde");
+        package.write("a = 3\n");
+
+        restore_to(&first, &package.manifest_path, &package.lock_path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&package.manifest_path).unwrap(),
+            "a = 1\n"
+        );
+        assert_eq!(
+            fs::read_to_string(&package.lock_path).unwrap(),
+            "# lock 1\n"
+        );
+        assert!(package.ids().is_empty());
+    }
+
+    #[test]
+    fn invalid_saved_manifests_are_not_restored() {
+        let package = Package::new("history-invalid");
+        package.write("a = 1\n");
+        let entry = package.record("add serde");
+        fs::write(entry.manifest_path(), "a = \n").unwrap();
+        package.write("a = 2\n");
+        assert!(restore_to(&entry, &package.manifest_path, &package.lock_path).is_err());
+        assert_eq!(
+            fs::read_to_string(&package.manifest_path).unwrap(),
+            "a = 2\n"
+        );
+        assert_eq!(package.ids(), vec![1]);
+    }
+
+    #[test]
+    fn only_the_latest_entries_are_kept() {
+        let package = Package::new("history-limit");
+        package.write("a = 1\n");
+        for _ in 0..MAX_ENTRIES + 2 {
+            package.record("update");
+        }
+        let ids = package.ids();
+        assert_eq!(ids.len(), MAX_ENTRIES);
+        assert_eq!(ids[0], 3);
+    }
+}
@@ -0,0 +1,19 @@
+pub mod audit;
+pub mod bisect;
+pub mod cargoreader;
+pub mod common;
+pub mod config;
+pub mod cratesio;
+pub mod dependency;
+pub mod format;
+pub mod history;
+pub mod licenses;
+pub mod lint;
+pub mod lockfile;
+pub mod policy;
+pub mod resolve;
+#[cfg(test)]
+mod test_support;
+pub mod unused;
+pub mod utils;
+pub mod verify;
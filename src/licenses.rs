@@ -0,0 +1,236 @@
+use crate::config::LicenseConfig;
+use crate::lockfile::Lockfile;
+use crate::utils::Result;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+/// What a crate's manifest says about its license.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum License {
+    Expression(String),
+    File(String),
+    /// The manifest sets neither `license` nor `license-file`.
+    Unspecified,
+    /// The crate's source isn't in the registry cache or vendor directory.
+    NotFound,
+}
+
+impl std::fmt::Display for License {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            License::Expression(expression) => write!(f, "{}", expression),
+            License::File(file) => write!(f, "license file '{}'", file),
+            License::Unspecified => write!(f, "no license"),
+            License::NotFound => write!(f, "source not found"),
+        }
+    }
+}
+
+/// Where a locked crate's unpacked source lives, looking in the vendor directory if there is one,
+/// or in every registry under `$CARGO_HOME/registry/src` otherwise.
+fn find_crate_source(
+    cargo_home: &Path,
+    vendor_dir: Option<&Path>,
+    name: &str,
+    version: &str,
+) -> Result<Option<PathBuf>> {
+    let versioned = format!("{}-{}", name, version);
+    if let Some(vendor_dir) = vendor_dir {
+        return Ok([vendor_dir.join(&versioned), vendor_dir.join(name)]
+            .into_iter()
+            .find(|dir| dir.join("Cargo.toml").is_file()));
+    }
+
+    let registry_src = cargo_home.join("registry").join("src");
+    if !registry_src.is_dir() {
+        return Ok(None);
+    }
+    for registry in fs::read_dir(registry_src)? {
+        let dir = registry?.path().join(&versioned);
+        if dir.join("Cargo.toml").is_file() {
+            return Ok(Some(dir));
+        }
+    }
+    Ok(None)
+}
+
+fn read_license(source_dir: &Path) -> Result<License> {
+    let manifest: Value = fs::read_to_string(source_dir.join("Cargo.toml"))?.parse()?;
+    let package = manifest.get("package");
+    let field = |key: &str| {
+        package
+            .and_then(|package| package.get(key))
+            .and_then(Value::as_str)
+            .map(str::to_owned)
+    };
+    Ok(match (field("license"), field("license-file")) {
+        (Some(expression), _) => License::Expression(expression),
+        (None, Some(file)) => License::File(file),
+        (None, None) => License::Unspecified,
+    })
+}
+
+/// The licenses of every locked registry package, with the packages using each one.
+pub fn license_inventory(
+    lockfile: &Lockfile,
+    cargo_home: &Path,
+    vendor_dir: Option<&Path>,
+) -> Result<BTreeMap<License, Vec<usize>>> {
+    let mut inventory: BTreeMap<License, Vec<usize>> = BTreeMap::new();
+    for (position, package) in lockfile.packages.iter().enumerate() {
+        if package.source.is_none() {
+            continue;
+        }
+        let license = match find_crate_source(
+            cargo_home,
+            vendor_dir,
+            &package.name,
+            &package.version.to_string(),
+        )? {
+            Some(source_dir) => read_license(&source_dir)?,
+            None => License::NotFound,
+        };
+        inventory.entry(license).or_default().push(position);
+    }
+    Ok(inventory)
+}
+
+fn tokenize_spdx(expression: &str) -> Vec<String> {
+    expression
+        .replace('(', " ( ")
+        .replace(')', " ) ")
+        .replace('/', " OR ")
+        .split_whitespace()
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Evaluates an SPDX expression with an allowed-license check for each license in it. `AND`
+/// binds tighter than `OR`, and `A WITH exception` is checked as `A`. Older crates' `A/B` is
+/// read as `A OR B`.
+fn spdx_satisfied(tokens: &[String], position: &mut usize, allowed: &dyn Fn(&str) -> bool) -> bool {
+    let mut any = spdx_and(tokens, position, allowed);
+    while tokens.get(*position).map(String::as_str) == Some("OR") {
+        *position += 1;
+        any |= spdx_and(tokens, position, allowed);
+    }
+    any
+}
+
+fn spdx_and(tokens: &[String], position: &mut usize, allowed: &dyn Fn(&str) -> bool) -> bool {
+    let mut all = spdx_term(tokens, position, allowed);
+    while tokens.get(*position).map(String::as_str) == Some("AND") {
+        *position += 1;
+        all &= spdx_term(tokens, position, allowed);
+    }
+    all
+}
+
+fn spdx_term(tokens: &[String], position: &mut usize, allowed: &dyn Fn(&str) -> bool) -> bool {
+    let token = tokens.get(*position).cloned().unwrap_or_default();
+    *position += 1;
+    let satisfied = if token == "(" {
+        let satisfied = spdx_satisfied(tokens, position, allowed);
+        *position += 1;
+        satisfied
+    } else {
+        allowed(token.trim_end_matches('+'))
+    };
+    if tokens.get(*position).map(String::as_str) == Some("WITH") {
+        *position += 2;
+    }
+    satisfied
+}
+
+/// Whether a license is acceptable under the config. Licenses that can't be read as an SPDX
+/// expression are only acceptable when the config sets `allow-unknown`.
+pub fn license_allowed(license: &License, config: &LicenseConfig) -> bool {
+    let allowed = |id: &str| {
+        !config.deny.iter().any(|denied| denied == id)
+            && (config.allow.is_empty() || config.allow.iter().any(|allowed| allowed == id))
+    };
+    match license {
+        License::Expression(expression) => {
+            let tokens = tokenize_spdx(expression);
+            spdx_satisfied(&tokens, &mut 0, &allowed)
+        }
+        _ => config.allow_unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn satisfied(expression: &str, allowed: &[&str]) -> bool {
+        spdx_satisfied(&tokenize_spdx(expression), &mut 0, &|id: &str| {
+            allowed.contains(&id)
+        })
+    }
+
+    fn config(allow: &[&str], deny: &[&str]) -> LicenseConfig {
+        LicenseConfig {
+            allow: allow.iter().map(|id| id.to_string()).collect(),
+            deny: deny.iter().map(|id| id.to_string()).collect(),
+            allow_unknown: false,
+        }
+    }
+
+    #[test]
+    fn or_needs_one_and_needs_all() {
+        assert!(satisfied("MIT OR Apache-2.0", &["Apache-2.0"]));
+        assert!(!satisfied("MIT AND Apache-2.0", &["Apache-2.0"]));
+        assert!(satisfied("MIT AND Apache-2.0", &["MIT", "Apache-2.0"]));
+        assert!(satisfied("MIT/Apache-2.0", &["MIT"]));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert!(satisfied("GPL-3.0 OR MIT AND Zlib", &["GPL-3.0"]));
+        assert!(!satisfied("GPL-3.0 OR MIT AND Zlib", &["MIT"]));
+        assert!(!satisfied("(GPL-3.0 OR MIT) AND Zlib", &["MIT"]));
+        assert!(satisfied("(GPL-3.0 OR MIT) AND Zlib", &["MIT", "Zlib"]));
+    }
+
+    #[test]
+    fn exceptions_and_or_later_are_checked_as_the_license() {
+        assert!(satisfied("Apache-2.0 WITH LLVM-exception", &["Apache-2.0"]));
+        assert!(satisfied("GPL-2.0+ OR MIT", &["GPL-2.0"]));
+        assert!(satisfied(
+            "(Apache-2.0 WITH LLVM-exception) AND MIT",
+            &["Apache-2.0", "MIT"]
+        ));
+    }
+
+    #[test]
+    fn license_config_denies_before_allowing() {
+        let expression = License::Expression("MIT OR GPL-3.0".to_owned());
+        assert!(license_allowed(&expression, &config(&[], &[])));
+        assert!(license_allowed(&expression, &config(&[], &["GPL-3.0"])));
+        assert!(!license_allowed(&expression, &config(&["MIT"], &["MIT"])));
+        assert!(!license_allowed(
+            &License::Expression("GPL-3.0".to_owned()),
+            &config(&[], &["GPL-3.0"])
+        ));
+    }
+
+    #[test]
+    fn unreadable_licenses_need_to_be_allowed_explicitly() {
+        for license in [
+            License::Unspecified,
+            License::NotFound,
+            License::File("LICENSE".to_owned()),
+        ] {
+            assert!(!license_allowed(&license, &config(&[], &[])));
+            assert!(!license_allowed(&license, &config(&[], &["MIT"])));
+            assert!(!license_allowed(&license, &config(&["MIT"], &[])));
+            let allow_unknown = LicenseConfig {
+                allow_unknown: true,
+                ..config(&["MIT"], &[])
+            };
+            assert!(license_allowed(&license, &allow_unknown));
+        }
+    }
+}
@@ -0,0 +1,754 @@
+use crate::common::{version_req_str, DEPENDENCY_SECTIONS};
+use crate::cratesio::{find_crates, IndexedCrate};
+use crate::utils::Result;
+use crates_index::Index;
+use semver::{Op, Version, VersionReq};
+use std::collections::HashMap;
+use toml::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LintId {
+    /// `*`, which crates.io rejects.
+    Wildcard,
+    /// `>=` or `>` with no upper bound.
+    Unbounded,
+    /// An `=` pin with no comment saying why.
+    ExactPin,
+    /// No version in the index matches, or the crate isn't in the index at all.
+    NoMatchingVersion,
+    /// Only yanked versions match.
+    YankedOnly,
+    /// The same crate in several sections with requirements no single version meets.
+    ConflictingRequirements,
+}
+
+impl LintId {
+    pub const ALL: [LintId; 6] = [
+        LintId::Wildcard,
+        LintId::Unbounded,
+        LintId::ExactPin,
+        LintId::NoMatchingVersion,
+        LintId::YankedOnly,
+        LintId::ConflictingRequirements,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LintId::Wildcard => "wildcard",
+            LintId::Unbounded => "unbounded",
+            LintId::ExactPin => "exact-pin",
+            LintId::NoMatchingVersion => "no-matching-version",
+            LintId::YankedOnly => "yanked-only",
+            LintId::ConflictingRequirements => "conflicting-requirements",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<LintId> {
+        LintId::ALL.into_iter().find(|id| id.as_str() == name)
+    }
+}
+
+impl std::fmt::Display for LintId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A dependency as it's declared in one section of the manifest.
+#[derive(Debug, Clone)]
+pub struct Declaration {
+    /// The section's key path, e.g. `["target", "cfg(unix)", "dependencies"]`.
+    pub section: Vec<String>,
+    pub name: String,
+    pub crate_name: String,
+    pub version_req: VersionReq,
+    /// The requirement as it's written.
+    pub requirement: String,
+    /// The line the dependency is declared on, counting from 1.
+    pub line: Option<usize>,
+    /// The line the requirement is written on, which differs from `line` for expanded tables.
+    pub requirement_line: Option<usize>,
+    /// The comments on that line and the ones right above it.
+    pub comments: Vec<String>,
+}
+
+impl Declaration {
+    pub fn section_str(&self) -> String {
+        self.section.join(".")
+    }
+
+    /// Whether a `carp:allow(...)` comment on the declaration suppresses a lint.
+    fn allows(&self, id: LintId) -> bool {
+        self.comments.iter().any(|comment| {
+            let comment: String = comment.chars().filter(|c| !c.is_whitespace()).collect();
+            comment
+                .split("carp:allow(")
+                .skip(1)
+                .filter_map(|allowed| allowed.split(')').next())
+                .flat_map(|allowed| allowed.split(','))
+                .any(|allowed| allowed == id.as_str())
+        })
+    }
+
+    /// Whether a comment on the declaration says something, rather than only allowing lints.
+    fn is_explained(&self) -> bool {
+        self.comments
+            .iter()
+            .any(|comment| !comment_text(comment).is_empty())
+    }
+}
+
+/// A comment's text without its `#`s and `carp:allow(...)` directives.
+fn comment_text(comment: &str) -> String {
+    let mut text = String::new();
+    let mut rest = comment.trim_start_matches('#');
+    while let Some(start) = rest.find("carp:allow(") {
+        text.push_str(&rest[..start]);
+        rest = rest[start..].split_once(')').map_or("", |(_, after)| after);
+    }
+    text.push_str(rest);
+    text.trim().to_owned()
+}
+
+#[derive(Debug, Clone)]
+pub struct Lint {
+    pub id: LintId,
+    /// The declaration in the list given to `lint` that the finding is about.
+    pub declaration: usize,
+    pub message: String,
+}
+
+/// Splits a dotted TOML key like `target.'cfg(unix)'.dependencies` into its unquoted parts.
+fn split_key_path(path: &str) -> Vec<String> {
+    let mut parts = vec![];
+    let mut part = String::new();
+    let mut quote = None;
+    for c in path.chars() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, '.') => parts.push(std::mem::take(&mut part).trim().to_owned()),
+            _ => part.push(c),
+        }
+    }
+    parts.push(part.trim().to_owned());
+    parts
+}
+
+/// Where a line's comment starts, ignoring `#`s inside strings.
+fn comment_start(line: &str) -> Option<usize> {
+    let mut quote = None;
+    for (position, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, '#') => return Some(position),
+            _ => (),
+        }
+    }
+    None
+}
+
+/// The line of every key and table header in a manifest, by its full key path.
+fn key_lines(text: &str) -> HashMap<Vec<String>, usize> {
+    let mut lines = HashMap::new();
+    let mut table: Vec<String> = vec![];
+    for (number, line) in text.lines().enumerate() {
+        let code = line[..comment_start(line).unwrap_or(line.len())].trim();
+        if let Some(header) = code.strip_prefix('[') {
+            table = split_key_path(header.trim_start_matches('[').trim_end_matches(']'));
+            lines.insert(table.clone(), number + 1);
+        } else if let Some((key, _)) = code.split_once('=') {
+            let mut path = table.clone();
+            path.extend(split_key_path(key));
+            lines.insert(path, number + 1);
+        }
+    }
+    lines
+}
+
+/// The comment on a line and every comment line right above it.
+fn comments_at(text: &str, line: usize) -> Vec<String> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut comments = vec![];
+    if let Some(comment) = lines
+        .get(line - 1)
+        .and_then(|code| comment_start(code).map(|start| &code[start..]))
+    {
+        comments.push(comment.to_owned());
+    }
+    for above in lines[..line - 1].iter().rev() {
+        match above.trim_start().strip_prefix('#') {
+            Some(_) => comments.push(above.trim().to_owned()),
+            None => break,
+        }
+    }
+    comments
+}
+
+fn section_declarations(
+    section: Vec<String>,
+    dependencies: &Value,
+    text: &str,
+    lines: &HashMap<Vec<String>, usize>,
+    declarations: &mut Vec<Declaration>,
+) -> Result<()> {
+    let dependencies = match dependencies.as_table() {
+        Some(dependencies) => dependencies,
+        None => return Ok(()),
+    };
+    for (name, value) in dependencies {
+        // Path and git dependencies without a version have nothing to lint.
+        let requirement = match value {
+            Value::String(requirement) => requirement,
+            Value::Table(table) => match table.get("version").and_then(Value::as_str) {
+                Some(requirement) => requirement,
+                None => continue,
+            },
+            _ => continue,
+        };
+        let version_req = requirement.parse().map_err(|err| {
+            format!(
+                "Could not parse the requirement of '{}' in [{}]: {}",
+                name,
+                section.join("."),
+                err
+            )
+        })?;
+        let crate_name = value
+            .get("package")
+            .and_then(Value::as_str)
+            .unwrap_or(name)
+            .to_owned();
+        let mut path = section.clone();
+        path.push(name.to_owned());
+        let line = lines.get(&path).copied();
+        path.push("version".to_owned());
+        let requirement_line = lines.get(&path).copied().or(line);
+        declarations.push(Declaration {
+            section: section.clone(),
+            name: name.to_owned(),
+            crate_name,
+            version_req,
+            requirement: requirement.to_owned(),
+            line,
+            requirement_line,
+            comments: line.map(|line| comments_at(text, line)).unwrap_or_default(),
+        });
+    }
+    Ok(())
+}
+
+/// Every dependency with a version requirement, from every dependency section of the manifest,
+/// including the target-specific ones. `text` is the manifest as written, for lines and comments.
+pub fn manifest_declarations(cargo_file: &Value, text: &str) -> Result<Vec<Declaration>> {
+    let lines = key_lines(text);
+    let mut declarations = vec![];
+    for section in DEPENDENCY_SECTIONS {
+        if let Some(dependencies) = cargo_file.get(section) {
+            section_declarations(
+                vec![section.to_owned()],
+                dependencies,
+                text,
+                &lines,
+                &mut declarations,
+            )?;
+        }
+    }
+    if let Some(targets) = cargo_file.get("target").and_then(Value::as_table) {
+        for (target, target_value) in targets {
+            for section in DEPENDENCY_SECTIONS {
+                if let Some(dependencies) = target_value.get(section) {
+                    section_declarations(
+                        vec!["target".to_owned(), target.to_owned(), section.to_owned()],
+                        dependencies,
+                        text,
+                        &lines,
+                        &mut declarations,
+                    )?;
+                }
+            }
+        }
+    }
+    Ok(declarations)
+}
+
+fn requirement_lints(declaration: &Declaration) -> Vec<(LintId, String)> {
+    let comparators = &declaration.version_req.comparators;
+    let mut lints = vec![];
+    if declaration.version_req == VersionReq::STAR {
+        lints.push((
+            LintId::Wildcard,
+            "crates.io rejects crates with wildcard requirements".to_owned(),
+        ));
+    }
+    let has_lower = comparators
+        .iter()
+        .any(|comparator| matches!(comparator.op, Op::Greater | Op::GreaterEq));
+    let has_upper = comparators
+        .iter()
+        .any(|comparator| !matches!(comparator.op, Op::Greater | Op::GreaterEq));
+    if has_lower && !has_upper {
+        lints.push((
+            LintId::Unbounded,
+            "there is no upper bound, so any future breaking release matches".to_owned(),
+        ));
+    }
+    if comparators
+        .iter()
+        .any(|comparator| comparator.op == Op::Exact)
+        && !declaration.is_explained()
+    {
+        lints.push((
+            LintId::ExactPin,
+            "exact pins get in the way of other crates' requirements; add a comment saying why"
+                .to_owned(),
+        ));
+    }
+    lints
+}
+
+fn index_lints(declaration: &Declaration, crte: Option<&IndexedCrate>) -> Vec<(LintId, String)> {
+    let crte = match crte {
+        Some(crte) => crte,
+        None => {
+            return vec![(
+                LintId::NoMatchingVersion,
+                format!("'{}' is not in the index", declaration.crate_name),
+            )]
+        }
+    };
+    if !crte.has_version(&declaration.version_req) {
+        return vec![(
+            LintId::NoMatchingVersion,
+            "no published version matches".to_owned(),
+        )];
+    }
+    if crte.select_version(&declaration.version_req).is_none() {
+        return vec![(
+            LintId::YankedOnly,
+            "every version that matches has been yanked".to_owned(),
+        )];
+    }
+    vec![]
+}
+
+/// The declarations of a crate in other sections whose requirement no version of it meets
+/// together with this one's.
+fn conflicting_sections(
+    declarations: &[Declaration],
+    position: usize,
+    crte: Option<&IndexedCrate>,
+) -> Vec<String> {
+    let declaration = &declarations[position];
+    let crte = match crte {
+        Some(crte) => crte,
+        None => return vec![],
+    };
+    declarations
+        .iter()
+        .enumerate()
+        .filter(|(other_position, other)| {
+            *other_position != position
+                && other.crate_name == declaration.crate_name
+                && other.section != declaration.section
+                && !crte.versions.iter().any(|version| {
+                    declaration.version_req.matches(version) && other.version_req.matches(version)
+                })
+        })
+        .map(|(_, other)| {
+            format!(
+                "{} ({})",
+                other.section_str(),
+                version_req_str(&other.version_req)
+            )
+        })
+        .collect()
+}
+
+/// Every declared crate from the index, or `None` for the ones it doesn't have.
+fn declared_crates<'a>(
+    index: &Index,
+    declarations: &'a [Declaration],
+) -> Result<HashMap<&'a str, Option<IndexedCrate>>> {
+    let mut names: Vec<&str> = declarations
+        .iter()
+        .map(|declaration| declaration.crate_name.as_str())
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+    Ok(names
+        .iter()
+        .copied()
+        .zip(find_crates(index, &names)?)
+        .collect())
+}
+
+/// Lints every declaration, leaving out the lints in `allowed` and the ones a declaration's
+/// comments suppress with `carp:allow(id, ...)`.
+pub fn lint(index: &Index, declarations: &[Declaration], allowed: &[LintId]) -> Result<Vec<Lint>> {
+    let crates = declared_crates(index, declarations)?;
+    let mut lints = vec![];
+    for (position, declaration) in declarations.iter().enumerate() {
+        let crte = crates[declaration.crate_name.as_str()].as_ref();
+        let mut found = requirement_lints(declaration);
+        found.extend(index_lints(declaration, crte));
+        let conflicts = conflicting_sections(declarations, position, crte);
+        if !conflicts.is_empty() {
+            found.push((
+                LintId::ConflictingRequirements,
+                format!("no version also meets {}", conflicts.join(", ")),
+            ));
+        }
+        for (id, message) in found {
+            if !allowed.contains(&id) && !declaration.allows(id) {
+                lints.push(Lint {
+                    id,
+                    declaration: position,
+                    message,
+                });
+            }
+        }
+    }
+    Ok(lints)
+}
+
+/// A new requirement for a declaration that fixes the lints found on it.
+#[derive(Debug, Clone)]
+pub struct Fix {
+    pub declaration: usize,
+    pub version_req: VersionReq,
+}
+
+/// The next breaking series after `version`, e.g. `2` for `1.4.2` and `0.5` for `0.4.1`.
+fn next_breaking(version: &Version) -> String {
+    if version.major > 0 {
+        format!("{}", version.major + 1)
+    } else if version.minor > 0 {
+        format!("0.{}", version.minor + 1)
+    } else {
+        format!("0.0.{}", version.patch + 1)
+    }
+}
+
+fn caret(version: &Version) -> Result<VersionReq> {
+    Ok(format!("^{}", version).parse()?)
+}
+
+/// A requirement that fixes one lint on its own, if the lint can be fixed.
+fn fix_requirement(
+    id: LintId,
+    version_req: &VersionReq,
+    crte: &IndexedCrate,
+) -> Result<Option<VersionReq>> {
+    Ok(match id {
+        LintId::Wildcard => match &crte.latest {
+            Some(latest) => Some(caret(latest)?),
+            None => None,
+        },
+        LintId::Unbounded => match crte.select_version(version_req) {
+            Some(selected) => Some(
+                format!(
+                    "{}, <{}",
+                    version_req,
+                    next_breaking(&crte.versions[selected])
+                )
+                .parse()?,
+            ),
+            None => None,
+        },
+        LintId::YankedOnly => {
+            let floor = crte
+                .versions
+                .iter()
+                .filter(|version| version_req.matches(version))
+                .min();
+            let raised = crte
+                .versions
+                .iter()
+                .zip(crte.crte.versions())
+                .filter(|(version, crate_version)| {
+                    version.pre.is_empty()
+                        && !crate_version.is_yanked()
+                        && floor.is_some_and(|floor| *version > floor)
+                })
+                .map(|(version, _)| version)
+                .min();
+            match raised {
+                Some(raised) => Some(caret(raised)?),
+                None => None,
+            }
+        }
+        _ => None,
+    })
+}
+
+/// New requirements for the declarations with fixable lints: `*` becomes a caret requirement on
+/// the latest version, open `>=` ranges get an upper bound before the next breaking release after
+/// the version they pick, floors that only match yanked versions are raised to the next version
+/// that isn't, and conflicting declarations all take the requirement that picks the newest version.
+pub fn fixes(index: &Index, declarations: &[Declaration], lints: &[Lint]) -> Result<Vec<Fix>> {
+    let crates = declared_crates(index, declarations)?;
+    let mut version_reqs: Vec<VersionReq> = declarations
+        .iter()
+        .map(|declaration| declaration.version_req.clone())
+        .collect();
+    for found in lints {
+        let declaration = &declarations[found.declaration];
+        if let Some(crte) = &crates[declaration.crate_name.as_str()] {
+            let current = &version_reqs[found.declaration];
+            if let Some(fixed) = fix_requirement(found.id, current, crte)? {
+                version_reqs[found.declaration] = fixed;
+            }
+        }
+    }
+
+    let conflicting: Vec<usize> = lints
+        .iter()
+        .filter(|found| found.id == LintId::ConflictingRequirements)
+        .map(|found| found.declaration)
+        .collect();
+    for &position in &conflicting {
+        let crate_name = &declarations[position].crate_name;
+        let crte = match &crates[crate_name.as_str()] {
+            Some(crte) => crte,
+            None => continue,
+        };
+        let group: Vec<usize> = conflicting
+            .iter()
+            .copied()
+            .filter(|&other| declarations[other].crate_name == *crate_name)
+            .collect();
+        let winner = group
+            .iter()
+            .filter_map(|&other| {
+                let selected = crte.select_version(&version_reqs[other])?;
+                Some((&crte.versions[selected], other))
+            })
+            .max()
+            .map(|(_, other)| version_reqs[other].clone());
+        if let Some(winner) = winner {
+            for other in group {
+                version_reqs[other] = winner.clone();
+            }
+        }
+    }
+
+    Ok(version_reqs
+        .into_iter()
+        .enumerate()
+        .filter(|(position, version_req)| declarations[*position].version_req != *version_req)
+        .map(|(declaration, version_req)| Fix {
+            declaration,
+            version_req,
+        })
+        .collect())
+}
+
+/// Replaces the requirement on a declaration's line, leaving the rest of the line as it was.
+fn replace_requirement(line: &str, declaration: &Declaration, requirement: &str) -> Result<String> {
+    let code = &line[..comment_start(line).unwrap_or(line.len())];
+    let search_from = code.find("version").or_else(|| code.find('=')).unwrap_or(0);
+    for quote in ['"', '\''] {
+        let quoted = format!("{}{}{}", quote, declaration.requirement, quote);
+        if let Some(found) = code[search_from..].find(&quoted) {
+            let start = search_from + found + 1;
+            let end = start + declaration.requirement.len();
+            return Ok(format!("{}{}{}", &line[..start], requirement, &line[end..]));
+        }
+    }
+    Err(format!(
+        "Could not find the requirement of '{}' in [{}].",
+        declaration.name,
+        declaration.section_str()
+    )
+    .into())
+}
+
+/// The manifest's text with the fixes applied, keeping its comments and formatting. The new text
+/// is parsed back and checked to declare the new requirements.
+pub fn apply_fixes(text: &str, declarations: &[Declaration], fixes: &[Fix]) -> Result<String> {
+    let mut lines: Vec<String> = text.lines().map(str::to_owned).collect();
+    for fix in fixes {
+        let declaration = &declarations[fix.declaration];
+        let line = declaration.requirement_line.ok_or(format!(
+            "Could not find the declaration of '{}' in [{}].",
+            declaration.name,
+            declaration.section_str()
+        ))?;
+        lines[line - 1] = replace_requirement(
+            &lines[line - 1],
+            declaration,
+            &version_req_str(&fix.version_req),
+        )?;
+    }
+    let mut new_text = lines.join("\n");
+    if text.ends_with('\n') {
+        new_text.push('\n');
+    }
+
+    let fixed = manifest_declarations(&new_text.parse()?, &new_text)?;
+    for fix in fixes {
+        let declaration = &declarations[fix.declaration];
+        let written = fixed.iter().find(|written| {
+            written.section == declaration.section && written.name == declaration.name
+        });
+        if written.map(|written| &written.version_req) != Some(&fix.version_req) {
+            return Err(
+                "The fixed manifest does not have the expected requirements, so it was not written."
+                    .into(),
+            );
+        }
+    }
+    Ok(new_text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn declarations(text: &str) -> Vec<Declaration> {
+        manifest_declarations(&text.parse().unwrap(), text).unwrap()
+    }
+
+    fn requirement_lint_ids(text: &str) -> Vec<LintId> {
+        let declarations = declarations(text);
+        requirement_lints(&declarations[0])
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    #[test]
+    fn finds_declarations_in_every_section() {
+        let found = declarations(
+            "[dependencies]\nserde = \"1\"\n\n[dev-dependencies]\nproptest = \"1\"\n\n\
+            [target.'cfg(unix)'.build-dependencies]\ncc = { version = \"1\" }\n",
+        );
+        let sections: Vec<_> = found
+            .iter()
+            .map(|declaration| (declaration.section_str(), declaration.line))
+            .collect();
+        assert_eq!(
+            sections,
+            vec![
+                ("dependencies".to_owned(), Some(2)),
+                ("dev-dependencies".to_owned(), Some(5)),
+                ("target.cfg(unix).build-dependencies".to_owned(), Some(8)),
+            ]
+        );
+    }
+
+    #[test]
+    fn exact_pins_need_an_explanation() {
+        assert_eq!(
+            requirement_lint_ids("[dependencies]\nserde = \"=1.0.100\"\n"),
+            vec![LintId::ExactPin]
+        );
+        assert_eq!(
+            requirement_lint_ids("[dependencies]\nserde = \"=1.0.100\" # carp:allow(wildcard)\n"),
+            vec![LintId::ExactPin]
+        );
+        assert!(requirement_lint_ids(
+            "[dependencies]\n# 1.0.101 breaks our derive.\nserde = \"=1.0.100\"\n"
+        )
+        .is_empty());
+        assert!(requirement_lint_ids(
+            "[dependencies]\nserde = \"=1.0.100\" # breaks after this; carp:allow(wildcard)\n"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn allow_comments_list_lint_ids() {
+        let declarations = declarations(
+            "[dependencies]\n# carp:allow( exact-pin, wildcard )\nserde = \"=1.0.100\"\n",
+        );
+        assert!(declarations[0].allows(LintId::ExactPin));
+        assert!(declarations[0].allows(LintId::Wildcard));
+        assert!(!declarations[0].allows(LintId::Unbounded));
+    }
+
+    fn fixed(id: LintId, requirement: &str, versions: &[(&str, bool)]) -> Option<String> {
+        let versions: Vec<_> = versions
+            .iter()
+            .map(|(version, yanked)| (*version, *yanked, "{}", &[][..]))
+            .collect();
+        let crte = crate::test_support::indexed_crate(&versions);
+        fix_requirement(id, &requirement.parse().unwrap(), &crte)
+            .unwrap()
+            .map(|version_req| version_req_str(&version_req))
+    }
+
+    #[test]
+    fn fixes_requirements() {
+        let versions = [
+            ("0.9.1", false),
+            ("1.0.0", false),
+            ("1.4.2", false),
+            ("2.0.0-rc.1", false),
+        ];
+        assert_eq!(
+            fixed(LintId::Wildcard, "*", &versions),
+            Some("1.4.2".to_owned())
+        );
+        assert_eq!(
+            fixed(LintId::Unbounded, ">=0.9", &versions),
+            Some(">=0.9, <2".to_owned())
+        );
+        assert_eq!(
+            fixed(LintId::Unbounded, ">=0.9", &versions[..1]),
+            Some(">=0.9, <0.10".to_owned())
+        );
+        assert_eq!(fixed(LintId::ExactPin, "=1.0.0", &versions), None);
+    }
+
+    #[test]
+    fn raises_floors_past_yanked_versions() {
+        let versions = [
+            ("1.2.0", true),
+            ("1.2.1", true),
+            ("1.2.2", false),
+            ("1.3.0", false),
+        ];
+        assert_eq!(
+            fixed(LintId::YankedOnly, "=1.2.0", &versions),
+            Some("1.2.2".to_owned())
+        );
+        assert_eq!(fixed(LintId::YankedOnly, "=1.2.0", &versions[..2]), None);
+    }
+
+    #[test]
+    fn applies_fixes_in_place() {
+        let text = "[dependencies]\n# Anything goes.\nrand = \"*\" # carp:allow(exact-pin)\n\n\
+            [dependencies.serde]\nfeatures = [\"derive\"]\nversion = '>=1.0'\n";
+        let declarations = declarations(text);
+        let fixes = vec![
+            Fix {
+                declaration: 0,
+                version_req: "^0.8.5".parse().unwrap(),
+            },
+            Fix {
+                declaration: 1,
+                version_req: ">=1.0, <2".parse().unwrap(),
+            },
+        ];
+        assert_eq!(
+            apply_fixes(text, &declarations, &fixes).unwrap(),
+            text.replace("\"*\"", "\"0.8.5\"")
+                .replace("'>=1.0'", "'>=1.0, <2'")
+        );
+    }
+
+    #[test]
+    fn apply_fixes_needs_the_written_requirement() {
+        let text = "[dependencies]\nrand = { package = \"rand\", version = \"*\" }\n";
+        let mut moved = declarations(text);
+        moved[0].requirement = "0.7".to_owned();
+        let fix = Fix {
+            declaration: 0,
+            version_req: "^0.8.5".parse().unwrap(),
+        };
+        assert!(apply_fixes(text, &moved, &[fix]).is_err());
+    }
+}
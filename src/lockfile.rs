@@ -0,0 +1,403 @@
+use crate::common::{compatible_series, get_lock_path};
+use crate::cratesio::{load_crates, INDEX_URL};
+use crate::resolve::{self, Requirement};
+use crate::utils::Result;
+use crates_index::{Index, CRATES_IO_HTTP_INDEX};
+use semver::{Version, VersionReq};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fs;
+use toml::Value;
+
+#[derive(Debug, Clone)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: Version,
+    /// Where the package came from, e.g. `registry+https://...`. Packages in the workspace and
+    /// path dependencies have none.
+    pub source: Option<String>,
+    /// Positions in `Lockfile::packages` of the packages this one depends on.
+    pub dependencies: Vec<usize>,
+}
+
+impl LockedPackage {
+    /// Whether the package comes from crates.io, through its git or its sparse index, and so can
+    /// be looked up in the index.
+    pub fn is_from_crates_io(&self) -> bool {
+        match self.source.as_deref() {
+            Some(source) => {
+                source.strip_prefix("registry+") == Some(INDEX_URL)
+                    || source == CRATES_IO_HTTP_INDEX
+            }
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Lockfile {
+    pub packages: Vec<LockedPackage>,
+}
+
+fn parse_locked_package(value: &Value) -> Result<(LockedPackage, Vec<String>)> {
+    let field = |key: &str| {
+        value.get(key).and_then(Value::as_str).ok_or(format!(
+            "Could not read the '{}' key of a package in the Cargo.lock file.",
+            key
+        ))
+    };
+    let name = field("name")?.to_owned();
+    let version = field("version")?.parse()?;
+    let source = value
+        .get("source")
+        .and_then(Value::as_str)
+        .map(str::to_owned);
+    let dependencies = match value.get("dependencies") {
+        Some(dependencies) => dependencies
+            .as_array()
+            .ok_or(format!(
+                "The dependencies of the locked package '{}' are not an array.",
+                name
+            ))?
+            .iter()
+            .filter_map(|dependency| dependency.as_str().map(str::to_owned))
+            .collect(),
+        None => vec![],
+    };
+    Ok((
+        LockedPackage {
+            name,
+            version,
+            source,
+            dependencies: vec![],
+        },
+        dependencies,
+    ))
+}
+
+/// Finds the package a lockfile dependency entry points at. Entries are `name`, `name version`
+/// or `name version (source)`, with only as much as it takes to tell packages apart.
+fn find_locked_dependency(packages: &[LockedPackage], entry: &str) -> Result<usize> {
+    let mut parts = entry.splitn(3, ' ');
+    let name = parts.next().unwrap_or_default();
+    let version = parts.next();
+    let source = parts
+        .next()
+        .map(|source| source.trim_start_matches('(').trim_end_matches(')'));
+
+    packages
+        .iter()
+        .position(|package| {
+            package.name == name
+                && version.is_none_or(|version| package.version.to_string() == version)
+                && source.is_none_or(|source| package.source.as_deref() == Some(source))
+        })
+        .ok_or(format!("Could not find the locked package for '{}'.", entry).into())
+}
+
+pub fn parse_lockfile(file_value: Value) -> Result<Lockfile> {
+    let package_values = file_value
+        .get("package")
+        .ok_or("Could not locate the package value in the Cargo.lock file given.")?
+        .as_array()
+        .ok_or("Could not parse the package value to an array in the Cargo.lock file given.")?;
+
+    let mut packages = vec![];
+    let mut entries = vec![];
+    for package_value in package_values {
+        let (package, dependencies) = parse_locked_package(package_value)?;
+        packages.push(package);
+        entries.push(dependencies);
+    }
+    for (position, dependencies) in entries.iter().enumerate() {
+        packages[position].dependencies = dependencies
+            .iter()
+            .map(|entry| find_locked_dependency(&packages, entry))
+            .collect::<Result<_>>()?;
+    }
+    Ok(Lockfile { packages })
+}
+
+pub fn read_lockfile() -> Result<Lockfile> {
+    let lock_file = fs::read_to_string(get_lock_path())?;
+    parse_lockfile(lock_file.parse()?)
+}
+
+impl Lockfile {
+    /// Packages in the workspace, which are the only ones without a source.
+    pub fn roots(&self) -> Vec<usize> {
+        (0..self.packages.len())
+            .filter(|position| self.packages[*position].source.is_none())
+            .collect()
+    }
+
+    /// Packages the workspace depends on directly.
+    pub fn direct_dependencies(&self) -> BTreeSet<usize> {
+        self.roots()
+            .iter()
+            .flat_map(|root| self.packages[*root].dependencies.iter().copied())
+            .filter(|position| self.packages[*position].source.is_some())
+            .collect()
+    }
+
+    /// Every package reachable from `start`, including it.
+    pub fn reachable(&self, start: usize) -> BTreeSet<usize> {
+        let mut reachable = BTreeSet::new();
+        let mut pending = vec![start];
+        while let Some(position) = pending.pop() {
+            if reachable.insert(position) {
+                pending.extend(self.packages[position].dependencies.iter().copied());
+            }
+        }
+        reachable
+    }
+
+    /// The shortest chain of packages from a workspace package down to `position`, both included.
+    pub fn path_to(&self, position: usize) -> Vec<usize> {
+        let mut parents: BTreeMap<usize, Option<usize>> =
+            self.roots().into_iter().map(|root| (root, None)).collect();
+        let mut queue: VecDeque<usize> = self.roots().into();
+        while let Some(current) = queue.pop_front() {
+            if current == position {
+                break;
+            }
+            for dependency in &self.packages[current].dependencies {
+                if !parents.contains_key(dependency) {
+                    parents.insert(*dependency, Some(current));
+                    queue.push_back(*dependency);
+                }
+            }
+        }
+
+        let mut path = vec![position];
+        while let Some(Some(parent)) = parents.get(path.last().unwrap()) {
+            path.push(*parent);
+        }
+        path.reverse();
+        path
+    }
+
+    pub fn find(&self, name: &str) -> Vec<usize> {
+        (0..self.packages.len())
+            .filter(|position| self.packages[*position].name == name)
+            .collect()
+    }
+
+    pub fn label(&self, position: usize) -> String {
+        let package = &self.packages[position];
+        format!("{} v{}", package.name, package.version)
+    }
+}
+
+/// A crate locked at versions from more than one semver-compatible series.
+pub struct DuplicateCrate {
+    pub name: String,
+    /// Each locked copy, lowest first, with the direct dependencies that pull it in.
+    pub copies: Vec<(usize, Vec<usize>)>,
+    /// Direct dependencies whose latest version would move them onto the newest copy's series:
+    /// the dependency, and the version to update it to.
+    pub suggestions: Vec<(usize, Version)>,
+}
+
+/// Finds crates locked in several semver-incompatible versions, and the direct dependency updates
+/// that would get rid of the older copies. Suggestions resolve each direct dependency's latest
+/// version with its default features.
+pub fn find_duplicates(index: &Index, lockfile: &Lockfile) -> Result<Vec<DuplicateCrate>> {
+    let direct = lockfile.direct_dependencies();
+    let reachable: BTreeMap<usize, BTreeSet<usize>> = direct
+        .iter()
+        .map(|position| (*position, lockfile.reachable(*position)))
+        .collect();
+
+    let mut by_name: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (position, package) in lockfile.packages.iter().enumerate() {
+        if package.source.is_some() {
+            by_name.entry(&package.name).or_default().push(position);
+        }
+    }
+
+    let mut duplicates = vec![];
+    for (name, mut positions) in by_name {
+        positions.sort_by(|a, b| {
+            lockfile.packages[*a]
+                .version
+                .cmp(&lockfile.packages[*b].version)
+        });
+        let series: BTreeSet<_> = positions
+            .iter()
+            .map(|position| compatible_series(&lockfile.packages[*position].version))
+            .collect();
+        if series.len() < 2 {
+            continue;
+        }
+
+        let copies: Vec<(usize, Vec<usize>)> = positions
+            .iter()
+            .map(|position| {
+                let pulled_by = direct
+                    .iter()
+                    .copied()
+                    .filter(|dependency| reachable[dependency].contains(position))
+                    .collect();
+                (*position, pulled_by)
+            })
+            .collect();
+        let newest = &lockfile.packages[*positions.last().unwrap()].version;
+        let mut outdated_pullers: BTreeSet<usize> = BTreeSet::new();
+        for (position, pulled_by) in &copies {
+            if compatible_series(&lockfile.packages[*position].version) != compatible_series(newest)
+            {
+                outdated_pullers.extend(pulled_by.iter().copied());
+            }
+        }
+
+        duplicates.push(DuplicateCrate {
+            name: name.to_owned(),
+            suggestions: collapsing_updates(index, lockfile, name, newest, &outdated_pullers)?,
+            copies,
+        });
+    }
+    Ok(duplicates)
+}
+
+/// The direct dependencies, out of `pullers`, whose latest version only uses `name` in the same
+/// series as `newest`. Only crates.io dependencies with a release can be looked up.
+fn collapsing_updates(
+    index: &Index,
+    lockfile: &Lockfile,
+    name: &str,
+    newest: &Version,
+    pullers: &BTreeSet<usize>,
+) -> Result<Vec<(usize, Version)>> {
+    let pullers: Vec<usize> = pullers
+        .iter()
+        .copied()
+        .filter(|position| lockfile.packages[*position].is_from_crates_io())
+        .collect();
+    let names: Vec<&str> = pullers
+        .iter()
+        .map(|position| lockfile.packages[*position].name.as_str())
+        .collect();
+    let mut suggestions = vec![];
+    for (position, crte) in pullers.iter().zip(load_crates(index, &names)?) {
+        let latest = match &crte.latest {
+            Some(latest) if latest > &lockfile.packages[*position].version => latest,
+            _ => continue,
+        };
+        let graph = resolve::resolve(
+            index,
+            &[Requirement {
+                crate_name: crte.name().to_owned(),
+                version_req: VersionReq::parse(&format!("={}", latest))?,
+                features: vec![],
+                default_features: true,
+            }],
+        )?;
+        let collapses = graph.find(name).iter().all(|found| {
+            compatible_series(&graph.crates[*found].version) == compatible_series(newest)
+        });
+        if collapses {
+            suggestions.push((*position, latest.clone()));
+        }
+    }
+    Ok(suggestions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCKFILE: &str = r#"
+version = 3
+
+[[package]]
+name = "demo"
+version = "0.1.0"
+dependencies = [
+ "rand 0.7.3",
+ "rand 0.8.5",
+ "helper",
+]
+
+[[package]]
+name = "helper"
+version = "0.1.0"
+dependencies = [
+ "getrandom 0.2.10 (sparse+https://index.crates.io/)",
+]
+
+[[package]]
+name = "rand"
+version = "0.7.3"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+dependencies = [
+ "getrandom 0.2.10 (registry+https://github.com/rust-lang/crates.io-index)",
+]
+
+[[package]]
+name = "rand"
+version = "0.8.5"
+source = "sparse+https://index.crates.io/"
+
+[[package]]
+name = "getrandom"
+version = "0.2.10"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "getrandom"
+version = "0.2.10"
+source = "sparse+https://index.crates.io/"
+"#;
+
+    fn lockfile() -> Lockfile {
+        parse_lockfile(LOCKFILE.parse().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn dependency_entries_point_at_the_right_package() {
+        let lockfile = lockfile();
+        let labels = |position: usize| -> Vec<String> {
+            lockfile.packages[position]
+                .dependencies
+                .iter()
+                .map(|dependency| lockfile.label(*dependency))
+                .collect()
+        };
+        assert_eq!(
+            labels(0),
+            vec!["rand v0.7.3", "rand v0.8.5", "helper v0.1.0"]
+        );
+        assert_eq!(lockfile.packages[1].dependencies, vec![5]);
+        assert_eq!(lockfile.packages[2].dependencies, vec![4]);
+    }
+
+    #[test]
+    fn unknown_dependency_entries_are_errors() {
+        let packages = lockfile().packages;
+        assert_eq!(find_locked_dependency(&packages, "rand 0.8.5").unwrap(), 3);
+        assert!(find_locked_dependency(&packages, "rand 0.6.0").is_err());
+        assert!(find_locked_dependency(&packages, "serde").is_err());
+    }
+
+    #[test]
+    fn walks_the_graph_from_the_workspace() {
+        let lockfile = lockfile();
+        assert_eq!(lockfile.roots(), vec![0, 1]);
+        assert_eq!(lockfile.direct_dependencies(), BTreeSet::from([2, 3, 5]));
+        assert_eq!(lockfile.reachable(2), BTreeSet::from([2, 4]));
+        assert_eq!(lockfile.path_to(5), vec![1, 5]);
+        assert_eq!(lockfile.find("getrandom"), vec![4, 5]);
+    }
+
+    #[test]
+    fn knows_crates_io_sources() {
+        let mut package = lockfile().packages[2].clone();
+        assert!(package.is_from_crates_io());
+        package.source = Some(CRATES_IO_HTTP_INDEX.to_owned());
+        assert!(package.is_from_crates_io());
+        package.source = Some("git+https://github.com/rust-random/rand#abc".to_owned());
+        assert!(!package.is_from_crates_io());
+        package.source = None;
+        assert!(!package.is_from_crates_io());
+    }
+}
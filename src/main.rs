@@ -1,24 +1,37 @@
-pub mod cargoreader;
 mod command;
-pub mod common;
-pub mod cratesio;
-pub mod dependency;
-pub mod utils;
 
-use crate::common::version_req_str;
-use crate::utils::{Join, One, Result};
-use dependency::{transform_dependency_version, Dependency};
-use std::env;
+use carp2::audit::{self, AuditTarget, Finding};
+use carp2::bisect::{self, Bump};
+use carp2::cargoreader::PackageFeatures;
+use carp2::common::{
+    compatible_series, get_advisory_db_path, get_cargo_home, get_cargo_path, get_lock_path,
+    get_package_dir, lock_manifest, version_req_str,
+};
+use carp2::config::{self, Config};
+use carp2::cratesio::{FeatureCheck, IndexedCrate, RequirementMatches};
+use carp2::dependency::{self, transform_dependency_version, Dependency};
+use carp2::history;
+use carp2::licenses::{self, License};
+use carp2::lint::{self, Declaration, Lint};
+use carp2::lockfile::{self, DuplicateCrate, Lockfile};
+use carp2::policy::{self, Problem, Violation};
+use carp2::resolve::{self, DependencyGraph, Impact};
+use carp2::utils::{write_file_atomically, Join, One, Result};
+use carp2::{cargoreader, cratesio, format, unused, verify};
+use crates_index::{DependencyKind, Index};
+use semver::{Version, VersionReq};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::{env, fs};
 
 fn get_dependencies() -> Result<Vec<Dependency>> {
-    cargoreader::read_cargo_file().and_then(|cargo_file| cargoreader::parse_cargo_file(cargo_file))
+    cargoreader::read_cargo_file().and_then(cargoreader::parse_cargo_file)
 }
 
-fn filter_dependencies(
-    check: &Vec<String>,
-    dependencies: Vec<Dependency>,
-) -> Result<Vec<Dependency>> {
-    let check_all_deps = check.len() == 0;
+fn filter_dependencies(check: &[String], dependencies: Vec<Dependency>) -> Result<Vec<Dependency>> {
+    let check_all_deps = check.is_empty();
     let deps_to_check: Vec<_> = dependencies
         .into_iter()
         .filter(|dependency| check_all_deps || check.iter().any(|arg| arg == &dependency.name))
@@ -27,7 +40,7 @@ fn filter_dependencies(
         Ok(deps_to_check)
     } else {
         let unknown_deps = check
-            .into_iter()
+            .iter()
             .filter(|arg| {
                 !deps_to_check
                     .iter()
@@ -38,6 +51,893 @@ fn filter_dependencies(
     }
 }
 
+fn read_manifest() -> Result<(Vec<Dependency>, PackageFeatures)> {
+    let cargo_file = cargoreader::read_cargo_file()?;
+    let package_features = cargoreader::parse_package_features(&cargo_file)?;
+    let dependencies = cargoreader::parse_cargo_file(cargo_file)?;
+    Ok((dependencies, package_features))
+}
+
+/// Resolves the manifest's dependencies, plus any `crate[@requirement]` candidates, against the
+/// index.
+fn resolve_manifest(candidates: &[String]) -> Result<DependencyGraph> {
+    let (dependencies, package_features) = read_manifest()?;
+
+    let mut requirements = resolve::manifest_requirements(&dependencies, &package_features)?;
+    for candidate in candidates {
+        requirements.push(resolve::parse_candidate(candidate)?);
+    }
+    cratesio::get_index().and_then(|index| resolve::resolve(&index, &requirements))
+}
+
+/// Drops or retargets updates that would lose features the manifest enables, saying why.
+fn apply_feature_checks(
+    index: &Index,
+    updates: Vec<(Dependency, Version)>,
+) -> Result<Vec<(Dependency, Version)>> {
+    let checks = cratesio::check_update_features(index, &updates)?;
+    let mut compatible = vec![];
+    for ((dependency, target), check) in updates.into_iter().zip(checks) {
+        match check {
+            FeatureCheck::Compatible => compatible.push((dependency, target)),
+            FeatureCheck::Fallback { missing, version } => {
+                println!(
+                    "! {} ({}): feature(s) '{}' missing from ({}), updating to ({}) instead",
+                    dependency.name,
+                    version_req_str(&dependency.version_req),
+                    missing.iter().join(","),
+                    target,
+                    version
+                );
+                compatible.push((dependency, version));
+            }
+            FeatureCheck::Incompatible { missing } => println!(
+                "! {} ({}): feature(s) '{}' missing from ({}) and no other newer version has them, not updating",
+                dependency.name,
+                version_req_str(&dependency.version_req),
+                missing.iter().join(","),
+                target
+            ),
+        }
+    }
+    Ok(compatible)
+}
+
+/// Retargets updates that would leave a pinned range to the newest version inside it, or drops
+/// them if there's nothing newer in it, saying why.
+fn apply_pins(
+    index: &Index,
+    updates: Vec<(Dependency, Version)>,
+    pinned: &BTreeMap<String, VersionReq>,
+) -> Result<Vec<(Dependency, Version)>> {
+    let mut kept = vec![];
+    for (dependency, target) in updates {
+        let pin = match pinned.get(&dependency.name) {
+            Some(pin) if !pin.matches(&target) => pin,
+            _ => {
+                kept.push((dependency, target));
+                continue;
+            }
+        };
+        let crte = cratesio::load_crate(index, dependency.crate_name())?;
+        let newest_pinned = crte
+            .select_version(pin)
+            .map(|position| crte.versions[position].clone())
+            .filter(|version| {
+                dependency
+                    .version
+                    .as_ref()
+                    .is_none_or(|current| version > current)
+            });
+        match newest_pinned {
+            Some(version) => {
+                println!(
+                    "! {} ({}): pinned to ({}), updating to ({}) instead of ({})",
+                    dependency.name,
+                    version_req_str(&dependency.version_req),
+                    version_req_str(pin),
+                    version,
+                    target
+                );
+                kept.push((dependency, version));
+            }
+            None => println!(
+                "! {} ({}): pinned to ({}), not updating to ({})",
+                dependency.name,
+                version_req_str(&dependency.version_req),
+                version_req_str(pin),
+                target
+            ),
+        }
+    }
+    Ok(kept)
+}
+
+/// Asks, for each outdated dependency, whether to skip it or update it to the highest version its
+/// requirement allows, to `latest`, or to a version typed in. A pick that isn't newer, leaves the
+/// dependency's pin or lacks a feature it enables is refused and asked again. Returns the picked
+/// updates, or nothing if stdin closes before every dependency is answered.
+fn pick_updates(
+    index: &Index,
+    updates: Vec<(Dependency, Version)>,
+    pinned: &BTreeMap<String, VersionReq>,
+) -> Result<Vec<(Dependency, Version)>> {
+    let names: Vec<&str> = updates
+        .iter()
+        .map(|(dependency, _)| dependency.crate_name())
+        .collect();
+    let crates = cratesio::load_crates(index, &names)?;
+    let mut lines = io::stdin().lock().lines();
+
+    let mut picked = vec![];
+    for ((dependency, latest), crte) in updates.iter().zip(&crates) {
+        let compatible = crte
+            .select_version(&dependency.version_req)
+            .map(|position| &crte.versions[position])
+            .filter(|version| {
+                dependency
+                    .version
+                    .as_ref()
+                    .is_none_or(|current| *version > current)
+            });
+        println!(
+            "{} ({}): compatible ({}), latest ({})",
+            dependency.name,
+            version_req_str(&dependency.version_req),
+            compatible.map_or("none".to_owned(), Version::to_string),
+            latest
+        );
+        loop {
+            print!("  [s]kip, [c]ompatible, [l]atest or a version (default latest): ");
+            io::stdout().flush()?;
+            let answer = match lines.next() {
+                Some(line) => line?.trim().to_owned(),
+                None => {
+                    println!();
+                    return Ok(vec![]);
+                }
+            };
+            let target = match answer.as_str() {
+                "s" | "skip" => break,
+                "" | "l" | "latest" => latest.clone(),
+                "c" | "compatible" => match compatible {
+                    Some(compatible) => compatible.clone(),
+                    None => {
+                        println!(
+                            "  Nothing newer matches ({}).",
+                            version_req_str(&dependency.version_req)
+                        );
+                        continue;
+                    }
+                },
+                version => match version.parse::<Version>() {
+                    Ok(version) => version,
+                    Err(_) => {
+                        println!(
+                            "  '{}' is not a published version of {}.",
+                            version,
+                            crte.name()
+                        );
+                        continue;
+                    }
+                },
+            };
+            let pin = pinned.get(&dependency.name);
+            if let Some(problem) = cratesio::picked_version_problem(dependency, crte, &target, pin)
+            {
+                println!("  {}", problem);
+                continue;
+            }
+            picked.push((dependency.clone(), target));
+            break;
+        }
+    }
+    Ok(picked)
+}
+
+/// Dependencies whose requirement allows versions older than the config's minimum for them,
+/// counting requirements with no lower bound, like `*` or `<2`.
+fn below_minimum<'a>(
+    config: &'a Config,
+    dependencies: &'a [Dependency],
+) -> Vec<(&'a Dependency, &'a Version)> {
+    dependencies
+        .iter()
+        .filter_map(|dependency| {
+            let minimum = config.minimum.get(&dependency.name)?;
+            match &dependency.version {
+                Some(version) if version >= minimum => None,
+                _ => Some((dependency, minimum)),
+            }
+        })
+        .collect()
+}
+
+/// What the project's carp config has against requiring `name` at `version_req`: a ban, a pin it
+/// leaves or a minimum it allows going below.
+fn requirement_policy_problems(
+    config: &Config,
+    name: &str,
+    version_req: &VersionReq,
+) -> Vec<String> {
+    let mut problems = vec![];
+    if let Some(banned) = config.ban_for_requirement(name, version_req) {
+        problems.push(format!("banned ({})", banned));
+    }
+    if let Some(pin) = config.pinned.get(name) {
+        if !dependency::requirements_overlap(pin, version_req) {
+            problems.push(format!("outside the pin ({})", version_req_str(pin)));
+        }
+    }
+    if let Some(minimum) = config.minimum.get(name) {
+        if dependency::get_version_from_version_req(version_req)
+            .is_none_or(|floor| &floor < minimum)
+        {
+            problems.push(format!("below the minimum ({})", minimum));
+        }
+    }
+    problems
+}
+
+/// The impact of moving each dependency to its new version, one at a time.
+fn update_impacts(updates: &[(Dependency, Version)]) -> Result<Vec<Impact>> {
+    let (dependencies, package_features) = read_manifest()?;
+    let index = cratesio::get_index()?;
+    let before = resolve::resolve(
+        &index,
+        &resolve::manifest_requirements(&dependencies, &package_features)?,
+    )?;
+    updates
+        .iter()
+        .map(|(dependency, new_version)| {
+            resolve::requirement_impact(
+                &index,
+                &dependencies,
+                &package_features,
+                &before,
+                &dependency.name,
+                &new_version.to_string(),
+            )
+        })
+        .collect()
+}
+
+/// Applies updates one at a time, running the verify command after each and rolling back the
+/// ones it fails on, or the one it couldn't run on. Returns the failing output for each rolled back
+/// update.
+fn verify_updates(updates: &[(Dependency, Version)], verify: &str) -> Result<Vec<Option<String>>> {
+    let command_line = verify::verify_command(verify)?;
+    let (passes, output) = verify::run_command(&command_line)?;
+    if !passes {
+        return Err(format!(
+            "'{}' fails before anything is updated:\n{}",
+            command_line.join(" "),
+            output
+        )
+        .into());
+    }
+
+    let mut outcomes = vec![];
+    for (dependency, new_version) in updates {
+        println!(
+            "* {} ({}) -> ({})",
+            dependency.name,
+            version_req_str(&dependency.version_req),
+            new_version
+        );
+        let entry = cargoreader::edit_dependencies(
+            &format!("update {}", dependency.name),
+            |dependencies| {
+                dependencies
+                    .into_iter()
+                    .map(|current| {
+                        if current.name == dependency.name {
+                            transform_dependency_version(&new_version.to_string(), current)
+                        } else {
+                            Ok(current)
+                        }
+                    })
+                    .collect()
+            },
+        )?;
+
+        match verify::run_command(&command_line) {
+            Ok((true, _)) => outcomes.push(None),
+            verified => {
+                history::restore(&entry)?;
+                outcomes.push(Some(verified?.1));
+            }
+        }
+    }
+    Ok(outcomes)
+}
+
+fn print_verify_summary(updates: &[(Dependency, Version)], outcomes: &[Option<String>]) {
+    let update_str = |(dependency, new_version): &(Dependency, Version)| {
+        format!(
+            "{} ({}) -> ({})",
+            dependency.name,
+            version_req_str(&dependency.version_req),
+            new_version
+        )
+    };
+    println!("\nKept:");
+    for (update, _) in updates
+        .iter()
+        .zip(outcomes)
+        .filter(|(_, failed)| failed.is_none())
+    {
+        println!("  {}", update_str(update));
+    }
+    println!("Rolled back:");
+    for (update, output) in updates
+        .iter()
+        .zip(outcomes)
+        .filter_map(|(update, failed)| Some((update, failed.as_ref()?)))
+    {
+        println!("  {}", update_str(update));
+        for line in output.lines() {
+            println!("    | {}", line);
+        }
+    }
+}
+
+/// The manifest and lockfile as they were, put back when this is dropped, so they're restored
+/// however bisecting ends.
+struct SavedManifest {
+    manifest: String,
+    lockfile: Option<String>,
+}
+
+impl SavedManifest {
+    fn read() -> Result<SavedManifest> {
+        Ok(SavedManifest {
+            manifest: fs::read_to_string(get_cargo_path())?,
+            lockfile: fs::read_to_string(get_lock_path()).ok(),
+        })
+    }
+}
+
+impl Drop for SavedManifest {
+    fn drop(&mut self) {
+        if let Err(err) = write_manifest_and_lockfile(&self.manifest, self.lockfile.as_deref()) {
+            eprintln!("ERROR putting back the manifest and lockfile: {}", err);
+        }
+    }
+}
+
+/// Writes the manifest and the lockfile, or removes the lockfile if there's none to write.
+fn write_manifest_and_lockfile(manifest: &str, lockfile: Option<&str>) -> Result<()> {
+    let _lock = lock_manifest()?;
+    write_file_atomically(&get_cargo_path(), manifest)?;
+    match lockfile {
+        Some(lockfile) => write_file_atomically(&get_lock_path(), lockfile),
+        None if get_lock_path().exists() => Ok(fs::remove_file(get_lock_path())?),
+        None => Ok(()),
+    }
+}
+
+/// Applies subsets of the bumps to the manifest, running the verify command on each, to find the
+/// smallest set of them that makes it fail. Every trial starts from `lockfile`, so cargo resolves
+/// it afresh instead of from what the previous trial locked.
+fn run_bisection(
+    after: &[Dependency],
+    bumps: &[Bump],
+    lockfile: Option<&str>,
+    command_line: &[String],
+) -> Result<Vec<usize>> {
+    let fails = |applied: &[usize]| -> Result<bool> {
+        let manifest = cargoreader::manifest_with_dependencies(bisect::dependencies_with(
+            after, bumps, applied,
+        ))?;
+        write_manifest_and_lockfile(&manifest, lockfile)?;
+        let (passes, _) = verify::run_command(command_line)?;
+        let applied_str = if applied.is_empty() {
+            "no bumps".to_owned()
+        } else {
+            applied
+                .iter()
+                .map(|position| bumps[*position].after.name.as_str())
+                .join(", ")
+        };
+        println!(
+            "  {}: {}",
+            applied_str,
+            if passes { "passes" } else { "fails" }
+        );
+        Ok(!passes)
+    };
+
+    let all: Vec<usize> = (0..bumps.len()).collect();
+    if !fails(&all)? {
+        return Err(format!(
+            "'{}' passes with every bump, so there is nothing to bisect.",
+            command_line.join(" ")
+        )
+        .into());
+    }
+    if fails(&[])? {
+        return Err(format!(
+            "'{}' fails even without any of the bumps.",
+            command_line.join(" ")
+        )
+        .into());
+    }
+    bisect::minimal_failing_set(bumps.len(), fails)
+}
+
+/// Finds the smallest set of the bumps made since a saved state of the manifest that makes the
+/// verify command fail, putting the manifest and lockfile back as they were afterwards, whether it
+/// succeeds or not. Trials start from the lockfile saved with that state, or the current one if
+/// none was.
+fn bisect_update(id: Option<&String>, verify: &str) -> Result<(Vec<Bump>, Vec<usize>)> {
+    let mut entries = history::list_history()?;
+    let entry = match id {
+        Some(id) => {
+            let id: usize = id
+                .parse()
+                .map_err(|_| format!("'{}' is not the id of a saved state.", id))?;
+            entries
+                .into_iter()
+                .find(|entry| entry.id == id)
+                .ok_or(format!("No saved state has the id '{}'.", id))?
+        }
+        None => entries
+            .pop()
+            .ok_or("No earlier states of the manifest are saved.")?,
+    };
+    let before =
+        cargoreader::parse_cargo_file(fs::read_to_string(entry.manifest_path())?.parse()?)?;
+    let after = get_dependencies()?;
+    let bumps = bisect::changed_dependencies(&before, &after);
+    if bumps.is_empty() {
+        return Err(format!("No dependency changed since before '{}'.", entry.reason).into());
+    }
+
+    let command_line = verify::verify_command(verify)?;
+    println!(
+        "Bisecting {} bump(s) since before '{}' with '{}'...",
+        bumps.len(),
+        entry.reason,
+        command_line.join(" ")
+    );
+    let original = SavedManifest::read()?;
+    let lockfile = if entry.has_lockfile() {
+        Some(fs::read_to_string(entry.lockfile_path())?)
+    } else {
+        original.lockfile.clone()
+    };
+    let culprits = run_bisection(&after, &bumps, lockfile.as_deref(), &command_line)?;
+    Ok((bumps, culprits))
+}
+
+fn print_impact(impact: &Impact) {
+    let versions_str =
+        |versions: &Vec<Version>| versions.iter().map(|v| format!("v{}", v)).join(", ");
+    if impact.is_empty() {
+        println!("    (no change to the dependency tree)");
+    }
+    for (name, versions) in &impact.added {
+        println!("    + {} {}", name, versions_str(versions));
+    }
+    for (name, versions) in &impact.removed {
+        println!("    - {} {}", name, versions_str(versions));
+    }
+    for (name, before, after) in &impact.changed {
+        println!(
+            "    ~ {} {} -> {}",
+            name,
+            versions_str(before),
+            versions_str(after)
+        );
+    }
+}
+
+fn print_duplicate(lockfile: &Lockfile, duplicate: &DuplicateCrate) {
+    println!("{}", duplicate.name);
+    for (position, pulled_by) in &duplicate.copies {
+        let version = &lockfile.packages[*position].version;
+        if pulled_by.is_empty() {
+            println!("  v{}", version);
+        } else {
+            println!(
+                "  v{} via {}",
+                version,
+                pulled_by
+                    .iter()
+                    .map(|direct| lockfile.label(*direct))
+                    .join(", ")
+            );
+        }
+    }
+    for (position, version) in &duplicate.suggestions {
+        println!("  update {} -> ({})", lockfile.label(*position), version);
+    }
+}
+
+fn remove_dependencies(names: &[String], reason: &str) -> Result<()> {
+    cargoreader::edit_dependencies(reason, |dependencies| {
+        Ok(dependencies
+            .into_iter()
+            .filter(|dependency| !names.contains(&dependency.name))
+            .collect())
+    })?;
+    Ok(())
+}
+
+fn find_unused_dependencies() -> Result<Vec<Dependency>> {
+    let cargo_file = cargoreader::read_cargo_file()?;
+    let sources = unused::source_files(&get_package_dir(), &cargo_file)?
+        .iter()
+        .map(fs::read_to_string)
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let dependencies = cargoreader::parse_cargo_file(cargo_file)?;
+    Ok(unused::unused_dependencies(&dependencies, &sources))
+}
+
+fn run_audit(db_path: Option<&str>) -> Result<Vec<Finding>> {
+    let db_path = db_path.map_or_else(get_advisory_db_path, PathBuf::from);
+    let advisories = audit::load_advisories(&db_path)?;
+    let lockfile = if get_lock_path().exists() {
+        Some(lockfile::read_lockfile()?)
+    } else {
+        None
+    };
+    let dependencies = get_dependencies()?;
+    let index = cratesio::get_index()?;
+    audit::audit(&index, &advisories, lockfile.as_ref(), &dependencies)
+}
+
+fn print_finding(finding: &Finding) {
+    let target = match &finding.target {
+        AuditTarget::Locked(version) => format!("locked v{}", version),
+        AuditTarget::Required(version_req) => format!("requires {}", version_req_str(version_req)),
+    };
+    let kind = match &finding.advisory.informational {
+        Some(informational) => format!(" [{}]", informational),
+        None => String::new(),
+    };
+    println!(
+        "! {} {} ({}){}: {}",
+        finding.advisory.id, finding.name, target, kind, finding.advisory.title
+    );
+    match &finding.upgrade {
+        Some(upgrade) => println!("    upgrade to ({})", upgrade),
+        None => println!("    no unaffected version to upgrade to"),
+    }
+}
+
+fn collect_licenses(vendor_dir: Option<&str>) -> Result<(Lockfile, BTreeMap<License, Vec<usize>>)> {
+    let lockfile = lockfile::read_lockfile()?;
+    let inventory =
+        licenses::license_inventory(&lockfile, &get_cargo_home(), vendor_dir.map(Path::new))?;
+    Ok((lockfile, inventory))
+}
+
+/// Checks the manifest, and the lockfile if there is one, against the config's policy.
+fn check_policy(config: &Config) -> Result<Vec<Violation>> {
+    let mut violations = policy::check_manifest(config, &get_dependencies()?)?;
+    if get_lock_path().exists() {
+        violations.extend(policy::check_lockfile(config, &lockfile::read_lockfile()?)?);
+    }
+    Ok(violations)
+}
+
+fn print_violation(violation: &Violation) {
+    match &violation.problem {
+        Problem::Banned(banned) => println!("! {}: banned ({})", violation.label, banned),
+        Problem::Source(source) => {
+            println!(
+                "! {}: from '{}', which is not allowed",
+                violation.label, source
+            )
+        }
+    }
+    if violation.path.is_empty() {
+        println!("    required by the manifest");
+    } else {
+        println!("    {}", violation.path.join(" -> "));
+    }
+}
+
+fn run_lint(config: &Config) -> Result<(Vec<Declaration>, Vec<Lint>)> {
+    let cargo_text = fs::read_to_string(get_cargo_path())?;
+    let declarations = lint::manifest_declarations(&cargo_text.parse()?, &cargo_text)?;
+    let index = cratesio::get_index()?;
+    let lints = lint::lint(&index, &declarations, &config.allowed_lints)?;
+    Ok((declarations, lints))
+}
+
+/// The manifest's text and the text formatted.
+fn format_current_manifest(config: &Config) -> Result<(String, String)> {
+    let cargo_text = fs::read_to_string(get_cargo_path())?;
+    let formatted = format::format_manifest_text(&cargo_text, config.tables)?;
+    Ok((cargo_text, formatted))
+}
+
+/// The manifest's text and the text with every fixable lint fixed.
+fn fix_lints(config: &Config) -> Result<(String, String)> {
+    let cargo_text = fs::read_to_string(get_cargo_path())?;
+    let declarations = lint::manifest_declarations(&cargo_text.parse()?, &cargo_text)?;
+    let index = cratesio::get_index()?;
+    let lints = lint::lint(&index, &declarations, &config.allowed_lints)?;
+    let fixes = lint::fixes(&index, &declarations, &lints)?;
+    let fixed = lint::apply_fixes(&cargo_text, &declarations, &fixes)?;
+    Ok((cargo_text, fixed))
+}
+
+/// Prints the lines that differ between two versions of a manifest with the same line count.
+fn print_line_diff(before: &str, after: &str) {
+    for (number, (old, new)) in before.lines().zip(after.lines()).enumerate() {
+        if old != new {
+            println!("line {}:", number + 1);
+            println!("- {}", old);
+            println!("+ {}", new);
+        }
+    }
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "yes"))
+}
+
+fn print_lint(declaration: &Declaration, lint: &Lint) {
+    let location = match declaration.line {
+        Some(line) => format!("[{}] line {}", declaration.section_str(), line),
+        None => format!("[{}]", declaration.section_str()),
+    };
+    println!(
+        "! {}: {} ({}) in {}: {}",
+        lint.id,
+        declaration.name,
+        version_req_str(&declaration.version_req),
+        location,
+        lint.message
+    );
+}
+
+fn print_requirement_matches(
+    crte: &IndexedCrate,
+    version_req: &VersionReq,
+    floor: Option<&Version>,
+    matches: &RequirementMatches,
+) {
+    let version_str = |position: usize| {
+        if crte.crte.versions()[position].is_yanked() {
+            format!("{} (yanked)", crte.versions[position])
+        } else {
+            crte.versions[position].to_string()
+        }
+    };
+    println!("{} ({})", crte.name(), version_req_str(version_req));
+    if let Some(floor) = floor {
+        println!("Lowest version named: {}", floor);
+    }
+
+    if matches.matching.is_empty() {
+        println!("\nNo versions match.");
+    } else {
+        println!("\nMatches {} versions:", matches.matching.len());
+        for &position in &matches.matching {
+            if Some(position) == matches.selected {
+                println!("  {} <- selected", version_str(position));
+            } else {
+                println!("  {}", version_str(position));
+            }
+        }
+    }
+    if matches.selected.is_none() && !matches.matching.is_empty() {
+        println!("\nCargo would select nothing: every match is yanked.");
+    }
+
+    println!("\nJust outside:");
+    println!(
+        "  below: {}",
+        matches.below.map_or("none".to_owned(), version_str)
+    );
+    println!(
+        "  above: {}",
+        matches.above.map_or("none".to_owned(), version_str)
+    );
+}
+
+/// Every declaration of a crate in the manifest, in any section, and the versions of it in the
+/// lockfile, for the projects that have them.
+fn crate_usage(crate_name: &str) -> Result<(Vec<Declaration>, Vec<Version>)> {
+    let declared = if get_cargo_path().is_file() {
+        let cargo_text = fs::read_to_string(get_cargo_path())?;
+        lint::manifest_declarations(&cargo_text.parse()?, &cargo_text)?
+            .into_iter()
+            .filter(|declaration| declaration.crate_name == crate_name)
+            .collect()
+    } else {
+        vec![]
+    };
+    let locked = if get_lock_path().is_file() {
+        let lockfile = lockfile::read_lockfile()?;
+        lockfile
+            .find(crate_name)
+            .into_iter()
+            .map(|position| lockfile.packages[position].version.clone())
+            .collect()
+    } else {
+        vec![]
+    };
+    Ok((declared, locked))
+}
+
+/// Lists a crate's versions by compatible series, newest first, marking the latest, the ones the
+/// manifest's requirements would select and the locked ones.
+fn print_versions(
+    crte: &IndexedCrate,
+    declared: &[Declaration],
+    locked: &[Version],
+    since: Option<&Version>,
+) {
+    let requirements = declared
+        .iter()
+        .map(|declaration| {
+            let requirement = version_req_str(&declaration.version_req);
+            if declared.len() > 1 {
+                format!("{} in {}", requirement, declaration.section_str())
+            } else {
+                requirement
+            }
+        })
+        .join(", ");
+    if declared.is_empty() {
+        println!("{}", crte.name())
+    } else {
+        println!("{} (required {})", crte.name(), requirements)
+    }
+    let required: Vec<&Version> = declared
+        .iter()
+        .filter_map(|declaration| crte.select_version(&declaration.version_req))
+        .map(|position| &crte.versions[position])
+        .collect();
+
+    let mut newest_first: Vec<_> = crte
+        .versions
+        .iter()
+        .zip(crte.crte.versions())
+        .filter(|(version, _)| since.is_none_or(|since| *version > since))
+        .collect();
+    if newest_first.is_empty() {
+        if let Some(since) = since {
+            println!("Nothing newer than {}.", since);
+        }
+        return;
+    }
+    newest_first.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    let mut series_start = 0;
+    while series_start < newest_first.len() {
+        let series = compatible_series(newest_first[series_start].0);
+        let series_len = newest_first[series_start..]
+            .iter()
+            .take_while(|(version, _)| compatible_series(version) == series)
+            .count();
+        let versions = newest_first[series_start..series_start + series_len]
+            .iter()
+            .map(|(version, crate_version)| {
+                let mut notes = vec![];
+                if crte.latest.as_ref() == Some(*version) {
+                    notes.push("latest");
+                }
+                if required.contains(version) {
+                    notes.push("required");
+                }
+                if locked.contains(version) {
+                    notes.push("locked");
+                }
+                if !version.pre.is_empty() {
+                    notes.push("prerelease");
+                }
+                if crate_version.is_yanked() {
+                    notes.push("yanked");
+                }
+                if notes.is_empty() {
+                    version.to_string()
+                } else {
+                    format!("{} ({})", version, notes.join(", "))
+                }
+            })
+            .join(", ");
+        println!("  {}: {}", series, versions);
+        series_start += series_len;
+    }
+}
+
+fn print_crate_info(crte: &IndexedCrate, selected: usize) {
+    let crate_version = &crte.crte.versions()[selected];
+    println!("{} ({})", crte.name(), crte.versions[selected]);
+    if let Some(links) = crate_version.links() {
+        println!("links: {}", links);
+    }
+    if let Some(rust_version) = crate_version.rust_version() {
+        println!("rust-version: {}", rust_version);
+    }
+
+    println!("\nVersions:");
+    let mut newest_first: Vec<_> = crte.versions.iter().zip(crte.crte.versions()).collect();
+    newest_first.sort_by(|(a, _), (b, _)| b.cmp(a));
+    let mut series_start = 0;
+    while series_start < newest_first.len() {
+        let series = compatible_series(newest_first[series_start].0);
+        let series_len = newest_first[series_start..]
+            .iter()
+            .take_while(|(version, _)| compatible_series(version) == series)
+            .count();
+        let versions = newest_first[series_start..series_start + series_len]
+            .iter()
+            .map(|(version, crate_version)| {
+                if crate_version.is_yanked() {
+                    format!("{} (yanked)", version)
+                } else {
+                    version.to_string()
+                }
+            })
+            .join(", ");
+        println!("  {}: {}", series, versions);
+        series_start += series_len;
+    }
+
+    let mut features: Vec<_> = crate_version.features().iter().collect();
+    features.sort_by_key(|(name, _)| (name.as_str() != "default", name.as_str()));
+    if !features.is_empty() {
+        println!("\nFeatures:");
+        for (name, enables) in features {
+            println!("  {} = [{}]", name, enables.iter().join(", "));
+        }
+    }
+
+    let mut dependencies: Vec<_> = crate_version.dependencies().iter().collect();
+    dependencies.sort_by_key(|dependency| dependency.name());
+    if !dependencies.is_empty() {
+        println!("\nDependencies ({}):", crte.versions[selected]);
+        for dependency in dependencies {
+            let mut notes = vec![];
+            match dependency.kind() {
+                DependencyKind::Normal => (),
+                DependencyKind::Dev => notes.push("dev".to_owned()),
+                DependencyKind::Build => notes.push("build".to_owned()),
+            }
+            if let Some(package) = dependency.package() {
+                notes.push(format!("package {}", package));
+            }
+            if dependency.is_optional() {
+                notes.push("optional".to_owned());
+            }
+            if !dependency.has_default_features() {
+                notes.push("no default features".to_owned());
+            }
+            if !dependency.features().is_empty() {
+                notes.push(format!(
+                    "features [{}]",
+                    dependency.features().iter().join(", ")
+                ));
+            }
+            if let Some(target) = dependency.target() {
+                notes.push(format!("target {}", target));
+            }
+            if notes.is_empty() {
+                println!("  {} ({})", dependency.name(), dependency.requirement());
+            } else {
+                println!(
+                    "  {} ({}) [{}]",
+                    dependency.name(),
+                    dependency.requirement(),
+                    notes.iter().join(", ")
+                );
+            }
+        }
+    }
+}
+
 fn main() {
     let args: Vec<_> = env::args().collect();
 
@@ -46,7 +946,19 @@ fn main() {
         return;
     }
 
-    match command::parse_args(&args[1..]) {
+    let config = if command::uses_config(&args[1]) {
+        match config::read_config() {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!("ERROR reading the carp config: {}", err);
+                process::exit(1);
+            }
+        }
+    } else {
+        Config::default()
+    };
+
+    match command::parse_args(&args[1..], &config.defaults) {
         Ok(command) => match command.name.as_str() {
             "list" => match get_dependencies() {
                 Ok(dependencies) => {
@@ -62,20 +974,30 @@ fn main() {
             },
             "check" => match get_dependencies() {
                 Ok(dependencies) => {
+                    let dependencies = if command.args.is_empty() {
+                        dependencies
+                            .into_iter()
+                            .filter(|dependency| !config.is_ignored(&dependency.name))
+                            .collect()
+                    } else {
+                        dependencies
+                    };
                     match filter_dependencies(&command.args, dependencies).and_then(
                         |deps_to_check| {
-                            cratesio::get_index().and_then(|index| {
+                            let out_of_date = cratesio::get_index().and_then(|index| {
                                 cratesio::out_of_date_dependencies(
                                     command.flags.strict,
                                     command.flags.only_strict,
                                     &index,
                                     &deps_to_check,
                                 )
-                            })
+                            })?;
+                            Ok((deps_to_check, out_of_date))
                         },
                     ) {
-                        Ok(out_of_date) => {
-                            if out_of_date.len() == 0 {
+                        Ok((deps_to_check, out_of_date)) => {
+                            let too_old = below_minimum(&config, &deps_to_check);
+                            if out_of_date.is_empty() && too_old.is_empty() {
                                 println!("Everything is up to date!")
                             } else {
                                 for (dependency, latest_version) in out_of_date {
@@ -86,6 +1008,14 @@ fn main() {
                                         latest_version
                                     )
                                 }
+                                for (dependency, minimum) in too_old {
+                                    println!(
+                                        "! {} ({}): below the minimum of ({})",
+                                        dependency.name,
+                                        version_req_str(&dependency.version_req),
+                                        minimum
+                                    )
+                                }
                             }
                         }
                         Err(err) => eprintln!("ERROR checking out of date dependencies: {}", err),
@@ -94,15 +1024,57 @@ fn main() {
                 Err(err) => eprintln!("ERROR reading dependencies: {}", err),
             },
             "add" => {
-                if command.args.len() < 1 {
-                    eprintln!("Usage: carp add <crate> [version]");
+                if command.args.is_empty() {
+                    eprintln!("Usage: carp add <crate> [version] [--force]");
+                    return;
+                }
+                if let Some(banned) = config.ban_for(&command.args[0], None) {
+                    eprintln!(
+                        "ERROR adding crate: '{}' is banned ({}) by the project's carp config.",
+                        command.args[0], banned
+                    );
                     return;
                 }
                 match cratesio::get_index().and_then(|index| {
                     if let Some(crte) = index.crate_(&command.args[0]) {
+                        if !command.flags.force && !config.allowlist.contains(&command.args[0]) {
+                            let dependencies = get_dependencies()?;
+                            let known: Vec<_> = dependencies
+                                .iter()
+                                .map(Dependency::crate_name)
+                                .chain(config.allowlist.iter().map(String::as_str))
+                                .collect();
+                            let targets = cratesio::typosquat_targets(
+                                &index,
+                                &IndexedCrate::new(crte.clone())?,
+                                &known,
+                            )?;
+                            if !targets.is_empty() {
+                                return Err(format!(
+                                    "'{}' is suspiciously close to {}, which you already depend on or trust. Use --force to add it anyway.",
+                                    &command.args[0],
+                                    targets.iter().map(|name| format!("'{}'", name)).join(", ")
+                                )
+                                .into());
+                            }
+                        }
                         cratesio::get_crate_latest_versions(&crte)
                     } else {
-                        Err(format!("Could not find crate '{}'", &command.args[0]).into())
+                        let suggestions = cratesio::similar_crate_names(&index, &command.args[0])?;
+                        if suggestions.is_empty() {
+                            Err(format!("Could not find crate '{}'", &command.args[0]).into())
+                        } else {
+                            Err(format!(
+                                "Could not find crate '{}'. Did you mean {}?",
+                                &command.args[0],
+                                suggestions
+                                    .iter()
+                                    .take(5)
+                                    .map(|name| format!("'{}'", name))
+                                    .join(", ")
+                            )
+                            .into())
+                        }
                     }
                 }) {
                     Ok((version_req, version)) => {
@@ -137,22 +1109,32 @@ fn main() {
                                 version_req: version_req.clone(),
                                 version: version.clone(),
                                 raw_toml_value: dependency::RawToml::String(
-                                    format!("{}", version_req_str(&version_req)).into(),
+                                    version_req_str(&version_req).into(),
                                 ),
                             }
                         };
-                        match cargoreader::read_cargo_file()
-                            .and_then(|cargo_file| cargoreader::parse_cargo_file(cargo_file))
+                        if let Some(banned) = config.ban_for_requirement(
+                            new_dependency.crate_name(),
+                            &new_dependency.version_req,
+                        ) {
+                            eprintln!(
+                                "ERROR adding crate: '{}' is banned ({}) by the project's carp config.",
+                                new_dependency.name, banned
+                            );
+                            return;
+                        }
+                        if let Some((_, minimum)) =
+                            below_minimum(&config, std::slice::from_ref(&new_dependency)).first()
                         {
-                            Ok(dependencies) => {
-                                let mut new_dependencies = dependencies;
-                                new_dependencies.push(new_dependency);
-                                match cargoreader::write_dependencies(new_dependencies) {
-                                    Ok(()) => (),
-                                    Err(err) => eprintln!("ERROR writing dependencies: {}", err),
-                                }
-                            }
-                            Err(err) => eprintln!("ERROR reading dependencies: {}", err),
+                            eprintln!(
+                                "ERROR adding crate: '{}' must be at least ({}) by the project's carp config.",
+                                new_dependency.name, minimum
+                            );
+                            return;
+                        }
+                        match cargoreader::add_dependency(new_dependency, &command.reason()) {
+                            Ok(()) => (),
+                            Err(err) => eprintln!("ERROR writing dependencies: {}", err),
                         }
                     }
                     Err(err) => eprintln!("ERROR finding crate: '{}'", err),
@@ -169,10 +1151,7 @@ fn main() {
                             .iter()
                             .any(|dependency| dependency.name == command.args[0])
                         {
-                            let new_dependencies = dependencies
-                                .into_iter()
-                                .filter(|dependency| dependency.name != command.args[0]);
-                            match cargoreader::write_dependencies(new_dependencies.collect()) {
+                            match remove_dependencies(&command.args, &command.reason()) {
                                 Ok(()) => println!("- {}", command.args[0]),
                                 Err(err) => eprintln!("ERROR writing dependencies: {}", err),
                             }
@@ -191,18 +1170,51 @@ fn main() {
                     match filter_dependencies(&command.args, dependencies.clone()).and_then(
                         |deps_to_check| {
                             cratesio::get_index().and_then(|index| {
-                                cratesio::out_of_date_dependencies(
+                                let out_of_date = cratesio::out_of_date_dependencies(
                                     command.flags.strict,
                                     command.flags.only_strict,
                                     &index,
                                     &deps_to_check,
-                                )
+                                )?;
+                                let out_of_date = apply_pins(&index, out_of_date, &config.pinned)?;
+                                let out_of_date = apply_feature_checks(&index, out_of_date)?;
+                                if command.flags.interactive && !out_of_date.is_empty() {
+                                    pick_updates(&index, out_of_date, &config.pinned)
+                                } else {
+                                    Ok(out_of_date)
+                                }
                             })
                         },
                     ) {
                         Ok(out_of_date) => {
-                            if out_of_date.len() == 0 {
-                                println!("Everything is up to date!")
+                            if out_of_date.is_empty() {
+                                if command.flags.interactive {
+                                    println!("Nothing to update.")
+                                } else {
+                                    println!("Everything is up to date!")
+                                }
+                            } else if command.flags.dry_run {
+                                match update_impacts(&out_of_date) {
+                                    Ok(impacts) => {
+                                        for ((dependency, latest_version), impact) in
+                                            out_of_date.iter().zip(impacts)
+                                        {
+                                            println!(
+                                                "* {} ({}) -> ({})",
+                                                dependency.name,
+                                                version_req_str(&dependency.version_req),
+                                                latest_version
+                                            );
+                                            print_impact(&impact)
+                                        }
+                                    }
+                                    Err(err) => eprintln!("ERROR resolving update impact: {}", err),
+                                }
+                            } else if let Some(verify) = &command.flags.verify {
+                                match verify_updates(&out_of_date, verify) {
+                                    Ok(outcomes) => print_verify_summary(&out_of_date, &outcomes),
+                                    Err(err) => eprintln!("ERROR verifying updates: {}", err),
+                                }
                             } else {
                                 for (dependency, latest_version) in &out_of_date {
                                     println!(
@@ -212,32 +1224,29 @@ fn main() {
                                         latest_version
                                     )
                                 }
-                                let update_result: Result<Vec<Dependency>> = dependencies
-                                    .into_iter()
-                                    .map(|dependency| {
-                                        if let Some((_, new_ver)) = out_of_date
-                                            .iter()
-                                            .one(|(ood, _)| ood.name == dependency.name)
-                                        {
-                                            transform_dependency_version(
-                                                &new_ver.to_string(),
-                                                dependency,
-                                            )
-                                        } else {
-                                            Ok(dependency)
-                                        }
-                                    })
-                                    .collect();
-
-                                match update_result {
-                                    Ok(updated_deps) => {
-                                        match cargoreader::write_dependencies(updated_deps) {
-                                            Ok(()) => (),
-                                            Err(err) => {
-                                                eprintln!("ERROR writing dependencies: {}", err)
-                                            }
-                                        }
-                                    }
+                                let updated = cargoreader::edit_dependencies(
+                                    &command.reason(),
+                                    |dependencies| {
+                                        dependencies
+                                            .into_iter()
+                                            .map(|dependency| {
+                                                if let Some((_, new_ver)) = out_of_date
+                                                    .iter()
+                                                    .one(|(ood, _)| ood.name == dependency.name)
+                                                {
+                                                    transform_dependency_version(
+                                                        &new_ver.to_string(),
+                                                        dependency,
+                                                    )
+                                                } else {
+                                                    Ok(dependency)
+                                                }
+                                            })
+                                            .collect()
+                                    },
+                                );
+                                match updated {
+                                    Ok(_) => (),
                                     Err(err) => eprintln!("ERROR updating dependencies: {}", err),
                                 }
                             }
@@ -247,6 +1256,439 @@ fn main() {
                 }
                 Err(err) => eprintln!("ERROR reading dependencies: {}", err),
             },
+            "search" => {
+                if command.args.len() != 1 {
+                    eprintln!("Usage: carp search <query> [--limit <count>]");
+                    return;
+                }
+                match cratesio::get_local_index()
+                    .and_then(|index| cratesio::search_crates(&index, &command.args[0]))
+                {
+                    Ok(results) => {
+                        if results.is_empty() {
+                            println!("No crates found matching '{}'.", command.args[0])
+                        } else {
+                            for result in results.iter().take(command.flags.limit.unwrap_or(20)) {
+                                println!(
+                                    "{} ({}){}",
+                                    result.name,
+                                    result.latest,
+                                    if result.yanked { " [yanked]" } else { "" }
+                                )
+                            }
+                        }
+                    }
+                    Err(err) => eprintln!("ERROR searching crates: {}", err),
+                }
+            }
+            "info" => {
+                if command.args.is_empty() || command.args.len() > 2 {
+                    eprintln!("Usage: carp info <crate> [version]");
+                    return;
+                }
+                match cratesio::get_local_index().and_then(|index| {
+                    let crte = cratesio::load_local_crate(&index, &command.args[0])?;
+                    let selected = match command.args.get(1) {
+                        Some(version_req) => {
+                            let version_req: VersionReq = version_req.parse()?;
+                            crte.select_version(&version_req).ok_or(format!(
+                                "No version of '{}' matches '{}'.",
+                                command.args[0], version_req
+                            ))?
+                        }
+                        None => crte.latest_index().ok_or(format!(
+                            "Could not find the latest version for crate '{}'.",
+                            command.args[0]
+                        ))?,
+                    };
+                    Ok((crte, selected))
+                }) {
+                    Ok((crte, selected)) => print_crate_info(&crte, selected),
+                    Err(err) => eprintln!("ERROR reading crate info: {}", err),
+                }
+            }
+            "versions" => {
+                if command.args.len() != 1 {
+                    eprintln!("Usage: carp versions <crate> [--since <version>]");
+                    return;
+                }
+                match cratesio::get_local_index().and_then(|index| {
+                    let since = match &command.flags.since {
+                        Some(since) => Some(
+                            since
+                                .parse::<Version>()
+                                .map_err(|_| format!("'{}' is not a version.", since))?,
+                        ),
+                        None => None,
+                    };
+                    let crte = cratesio::load_local_crate(&index, &command.args[0])?;
+                    let (declared, locked) = crate_usage(crte.name())?;
+                    Ok((crte, declared, locked, since))
+                }) {
+                    Ok((crte, declared, locked, since)) => {
+                        print_versions(&crte, &declared, &locked, since.as_ref())
+                    }
+                    Err(err) => eprintln!("ERROR listing versions: {}", err),
+                }
+            }
+            "explain" => {
+                if command.args.len() < 2 {
+                    eprintln!("Usage: carp explain <crate> <requirement>");
+                    return;
+                }
+                // Requirements like `>=0.3, <0.5` come in as several arguments unless they're quoted.
+                let requirement = command.args[1..].join(" ");
+                match cratesio::get_local_index().and_then(|index| {
+                    let crte = cratesio::load_local_crate(&index, &command.args[0])?;
+                    let (version_req, floor) = dependency::get_versions_from_str(&requirement)
+                        .map_err(|err| {
+                            format!("'{}' is not a requirement: {}", requirement, err)
+                        })?;
+                    let matches =
+                        cratesio::requirement_matches(&crte, &version_req, floor.as_ref());
+                    Ok((crte, version_req, floor, matches))
+                }) {
+                    Ok((crte, version_req, floor, matches)) => {
+                        print_requirement_matches(&crte, &version_req, floor.as_ref(), &matches)
+                    }
+                    Err(err) => eprintln!("ERROR explaining the requirement: {}", err),
+                }
+            }
+            "tree" => match resolve_manifest(&command.args) {
+                Ok(graph) => {
+                    if command.flags.duplicates {
+                        let duplicates = graph.duplicates();
+                        if duplicates.is_empty() {
+                            println!("No crates are resolved to more than one version.")
+                        }
+                        for positions in duplicates {
+                            for line in graph.tree_lines(
+                                &positions,
+                                &graph.dependents(),
+                                command.flags.depth,
+                            ) {
+                                println!("{}", line)
+                            }
+                            println!()
+                        }
+                    } else if let Some(invert) = &command.flags.invert {
+                        let positions = graph.find(invert);
+                        if positions.is_empty() {
+                            eprintln!("ERROR inverting tree: '{}' is not in the tree", invert);
+                            return;
+                        }
+                        for line in
+                            graph.tree_lines(&positions, &graph.dependents(), command.flags.depth)
+                        {
+                            println!("{}", line)
+                        }
+                    } else {
+                        let dependencies: Vec<_> = graph
+                            .crates
+                            .iter()
+                            .map(|resolved| resolved.dependencies.clone())
+                            .collect();
+                        for line in
+                            graph.tree_lines(&graph.roots, &dependencies, command.flags.depth)
+                        {
+                            println!("{}", line)
+                        }
+                    }
+                }
+                Err(err) => eprintln!("ERROR resolving dependency tree: {}", err),
+            },
+            "impact" => {
+                let candidate = match command.args.first().and_then(|arg| arg.split_once('@')) {
+                    Some(candidate) if command.args.len() == 1 => candidate,
+                    _ => {
+                        eprintln!("Usage: carp impact <crate>@<version>");
+                        return;
+                    }
+                };
+                match read_manifest().and_then(|(dependencies, package_features)| {
+                    let index = cratesio::get_index()?;
+                    let before = resolve::resolve(
+                        &index,
+                        &resolve::manifest_requirements(&dependencies, &package_features)?,
+                    )?;
+                    resolve::requirement_impact(
+                        &index,
+                        &dependencies,
+                        &package_features,
+                        &before,
+                        candidate.0,
+                        candidate.1,
+                    )
+                }) {
+                    Ok(impact) => {
+                        println!("* {} -> ({})", candidate.0, candidate.1);
+                        print_impact(&impact);
+                        if let Ok(version_req) = candidate.1.parse() {
+                            for problem in
+                                requirement_policy_problems(&config, candidate.0, &version_req)
+                            {
+                                println!(
+                                    "! {} ({}) is {} in the project's carp config",
+                                    candidate.0, candidate.1, problem
+                                )
+                            }
+                        }
+                    }
+                    Err(err) => eprintln!("ERROR resolving update impact: {}", err),
+                }
+            }
+            "duplicates" => match lockfile::read_lockfile().and_then(|lockfile| {
+                let index = cratesio::get_index()?;
+                let duplicates = lockfile::find_duplicates(&index, &lockfile)?;
+                Ok((lockfile, duplicates))
+            }) {
+                Ok((lockfile, duplicates)) => {
+                    if duplicates.is_empty() {
+                        println!("No crates are locked at more than one incompatible version.")
+                    }
+                    for duplicate in duplicates {
+                        print_duplicate(&lockfile, &duplicate)
+                    }
+                }
+                Err(err) => eprintln!("ERROR finding duplicate crates: {}", err),
+            },
+            "unused" => match find_unused_dependencies() {
+                Ok(unused) => {
+                    if unused.is_empty() {
+                        println!("Every dependency is used!")
+                    } else if command.flags.remove {
+                        let names: Vec<_> = unused
+                            .iter()
+                            .map(|dependency| dependency.name.to_owned())
+                            .collect();
+                        match remove_dependencies(&names, &command.reason()) {
+                            Ok(()) => {
+                                for name in names {
+                                    println!("- {}", name)
+                                }
+                            }
+                            Err(err) => eprintln!("ERROR writing dependencies: {}", err),
+                        }
+                    } else {
+                        for dependency in unused {
+                            println!(
+                                "? {} ({})",
+                                dependency.name,
+                                version_req_str(&dependency.version_req)
+                            )
+                        }
+                    }
+                }
+                Err(err) => eprintln!("ERROR finding unused dependencies: {}", err),
+            },
+            "audit" => match run_audit(command.flags.db.as_deref()) {
+                Ok(findings) => {
+                    if findings.is_empty() {
+                        println!("No known advisories affect your dependencies.")
+                    }
+                    for finding in &findings {
+                        print_finding(finding)
+                    }
+                }
+                Err(err) => eprintln!("ERROR auditing dependencies: {}", err),
+            },
+            "licenses" => match collect_licenses(command.flags.vendor.as_deref()) {
+                Ok((lockfile, inventory)) => {
+                    let mut violations = vec![];
+                    for (license, positions) in &inventory {
+                        println!(
+                            "{} ({}): {}",
+                            license,
+                            positions.len(),
+                            positions
+                                .iter()
+                                .map(|position| lockfile.label(*position))
+                                .join(", ")
+                        );
+                        if !licenses::license_allowed(license, &config.licenses) {
+                            violations
+                                .extend(positions.iter().map(|position| (*position, license)));
+                        }
+                    }
+                    for (position, license) in &violations {
+                        println!(
+                            "! {}: {} is not allowed",
+                            lockfile.label(*position),
+                            license
+                        );
+                    }
+                    if !violations.is_empty() {
+                        process::exit(1);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("ERROR collecting licenses: {}", err);
+                    process::exit(1);
+                }
+            },
+
+            "policy" => {
+                if command.args.first().map(String::as_str) != Some("check") {
+                    eprintln!("Usage: carp policy check");
+                    return;
+                }
+                match check_policy(&config) {
+                    Ok(violations) => {
+                        if violations.is_empty() {
+                            println!("No policy violations found.")
+                        }
+                        for violation in &violations {
+                            print_violation(violation)
+                        }
+                        if !violations.is_empty() {
+                            process::exit(1);
+                        }
+                    }
+                    Err(err) => {
+                        eprintln!("ERROR checking policy: {}", err);
+                        process::exit(1);
+                    }
+                }
+            }
+
+            "history" => match history::list_history() {
+                Ok(entries) => {
+                    if entries.is_empty() {
+                        println!("No earlier states of the manifest are saved.")
+                    }
+                    for entry in entries.iter().rev() {
+                        println!(
+                            "{}: before '{}', {}",
+                            entry.id,
+                            entry.reason,
+                            history::time_ago(entry.time)
+                        )
+                    }
+                }
+                Err(err) => eprintln!("ERROR reading history: {}", err),
+            },
+            "undo" => {
+                if command.args.len() > 1 {
+                    eprintln!("Usage: carp undo [id]");
+                    return;
+                }
+                match history::list_history().and_then(|entries| {
+                    let entry = match command.args.first() {
+                        Some(id) => {
+                            let id: usize = id
+                                .parse()
+                                .map_err(|_| format!("'{}' is not the id of a saved state.", id))?;
+                            entries
+                                .into_iter()
+                                .find(|entry| entry.id == id)
+                                .ok_or(format!("No saved state has the id '{}'.", id))?
+                        }
+                        None => entries
+                            .into_iter()
+                            .last()
+                            .ok_or("No earlier states of the manifest are saved.")?,
+                    };
+                    history::restore(&entry)?;
+                    Ok(entry)
+                }) {
+                    Ok(entry) => println!("Restored the manifest from before '{}'.", entry.reason),
+                    Err(err) => eprintln!("ERROR undoing: {}", err),
+                }
+            }
+
+            "bisect-update" => {
+                if command.args.len() > 1 {
+                    eprintln!("Usage: carp bisect-update [id] [--verify=<check|test|command>]");
+                    return;
+                }
+                match bisect_update(
+                    command.args.first(),
+                    command.flags.verify.as_deref().unwrap_or("check"),
+                ) {
+                    Ok((bumps, culprits)) => {
+                        println!("\nThe failure comes from:");
+                        for position in culprits {
+                            let bump = &bumps[position];
+                            println!(
+                                "* {} ({}) -> ({})",
+                                bump.after.name,
+                                version_req_str(&bump.before.version_req),
+                                version_req_str(&bump.after.version_req)
+                            )
+                        }
+                    }
+                    Err(err) => eprintln!("ERROR bisecting the update: {}", err),
+                }
+            }
+
+            "fmt" => match format_current_manifest(&config) {
+                Ok((current, formatted)) => {
+                    if current == formatted {
+                        println!("The manifest is already formatted.")
+                    } else if command.flags.check {
+                        println!("The manifest is not formatted. Run 'carp fmt' to format it.");
+                        process::exit(1);
+                    } else {
+                        match cargoreader::replace_manifest(&current, &formatted, &command.reason())
+                        {
+                            Ok(()) => println!("Formatted the manifest."),
+                            Err(err) => eprintln!("ERROR writing the manifest: {}", err),
+                        }
+                    }
+                }
+                Err(err) => {
+                    eprintln!("ERROR formatting the manifest: {}", err);
+                    if command.flags.check {
+                        process::exit(1);
+                    }
+                }
+            },
+
+            "lint" if command.flags.fix => match fix_lints(&config) {
+                Ok((current, fixed)) => {
+                    if current == fixed {
+                        println!("Nothing to fix.");
+                        return;
+                    }
+                    print_line_diff(&current, &fixed);
+                    if command.flags.dry_run {
+                        return;
+                    }
+                    let apply = if command.flags.force {
+                        Ok(true)
+                    } else {
+                        confirm("Apply these fixes?")
+                    };
+                    match apply {
+                        Ok(true) => (),
+                        Ok(false) => return,
+                        Err(err) => {
+                            eprintln!("ERROR reading the answer: {}", err);
+                            return;
+                        }
+                    }
+                    match cargoreader::replace_manifest(&current, &fixed, &command.reason()) {
+                        Ok(()) => println!("Fixed the manifest. Run 'carp lint' for what's left."),
+                        Err(err) => eprintln!("ERROR writing the manifest: {}", err),
+                    }
+                }
+                Err(err) => eprintln!("ERROR fixing the manifest: {}", err),
+            },
+
+            "lint" => match run_lint(&config) {
+                Ok((declarations, lints)) => {
+                    if lints.is_empty() {
+                        println!("No problems found.")
+                    }
+                    for found in &lints {
+                        print_lint(&declarations[found.declaration], found)
+                    }
+                    if !lints.is_empty() {
+                        process::exit(1);
+                    }
+                }
+                Err(err) => eprintln!("ERROR linting the manifest: {}", err),
+            },
 
             unknown_command => eprintln!("Unknown command: {}", unknown_command),
         },
@@ -0,0 +1,335 @@
+use crate::common::{get_cargo_home, get_package_dir, version_req_str};
+use crate::config::{BannedCrate, Config};
+use crate::cratesio::INDEX_URL;
+use crate::dependency::Dependency;
+use crate::lockfile::Lockfile;
+use crate::utils::Result;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use toml::Value;
+
+/// What the `registries` list calls crates.io, besides its index URLs.
+const CRATES_IO: &str = "crates-io";
+const CRATES_IO_SPARSE_URL: &str = "https://index.crates.io/";
+
+#[derive(Debug, Clone)]
+pub enum Problem {
+    Banned(BannedCrate),
+    /// The crate comes from a registry or repository that isn't allowed.
+    Source(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Violation {
+    /// The offending crate, e.g. `rand v0.8.5`, or `rand (0.8)` for a manifest requirement.
+    pub label: String,
+    pub problem: Problem,
+    /// The locked packages from the workspace down to the offending crate, or nothing for
+    /// requirements in the manifest.
+    pub path: Vec<String>,
+}
+
+/// The URL of a lockfile source, without its kind and any git reference, e.g.
+/// `git+https://github.com/a/b?branch=main#abc` becomes `https://github.com/a/b`.
+fn source_url(source: &str) -> &str {
+    let url = source.split_once('+').map_or(source, |(_, url)| url);
+    url.split(['?', '#']).next().unwrap_or(url)
+}
+
+fn same_url(a: &str, b: &str) -> bool {
+    a.trim_end_matches('/') == b.trim_end_matches('/')
+}
+
+/// Whether the `registries` list allows a source, which is a URL or a registry name. An empty list
+/// allows anything.
+fn source_allowed(source: &str, registries: &[String]) -> bool {
+    let is_crates_io = |source: &str| {
+        source == CRATES_IO || same_url(source, INDEX_URL) || same_url(source, CRATES_IO_SPARSE_URL)
+    };
+    registries.is_empty()
+        || registries.iter().any(|registry| {
+            same_url(registry, source) || (is_crates_io(source) && is_crates_io(registry))
+        })
+}
+
+/// The cargo config files that apply to the package, closest first: `.cargo/config.toml` in the
+/// package's directory and each one above it, then `$CARGO_HOME/config.toml`.
+fn cargo_config_paths() -> Vec<PathBuf> {
+    let cargo_home = get_cargo_home();
+    let package_dir = get_package_dir();
+    package_dir
+        .ancestors()
+        .map(|dir| dir.join(".cargo"))
+        .filter(|dir| *dir != cargo_home)
+        .chain(std::iter::once(cargo_home.clone()))
+        .flat_map(|dir| [dir.join("config.toml"), dir.join("config")])
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// The index URL cargo uses for a registry name, from `CARGO_REGISTRIES_<NAME>_INDEX` or the
+/// `[registries.<name>]` table of its config files.
+fn registry_index(name: &str) -> Result<Option<String>> {
+    let variable = format!(
+        "CARGO_REGISTRIES_{}_INDEX",
+        name.to_uppercase().replace('-', "_")
+    );
+    if let Ok(index) = env::var(variable) {
+        return Ok(Some(index));
+    }
+    for path in cargo_config_paths() {
+        let config: Value = fs::read_to_string(&path)?
+            .parse()
+            .map_err(|err| format!("Could not parse '{}': {}", path.display(), err))?;
+        let index = config
+            .get("registries")
+            .and_then(|registries| registries.get(name))
+            .and_then(|registry| registry.get("index"))
+            .and_then(Value::as_str);
+        if let Some(index) = index {
+            return Ok(Some(index.to_owned()));
+        }
+    }
+    Ok(None)
+}
+
+/// The config's `registries`, with the index URL of each one that's a registry name cargo knows.
+fn allowed_registries(config: &Config) -> Result<Vec<String>> {
+    let mut registries = vec![];
+    for registry in &config.registries {
+        registries.push(registry.clone());
+        if !registry.contains("://") {
+            if let Some(index) = registry_index(registry)? {
+                registries.push(source_url(&index).to_owned());
+            }
+        }
+    }
+    Ok(registries)
+}
+
+/// Checks the manifest's requirements against the config's banned crates and allowed registries.
+/// A requirement is banned when any version it allows is, and path dependencies are allowed
+/// whatever their source. Registries are named in the manifest, so they're looked up in cargo's
+/// config and allowed by either their name or their index URL.
+pub fn check_manifest(config: &Config, dependencies: &[Dependency]) -> Result<Vec<Violation>> {
+    let registries = allowed_registries(config)?;
+    let mut violations = vec![];
+    for dependency in dependencies {
+        let label = format!(
+            "{} ({})",
+            dependency.name,
+            version_req_str(&dependency.version_req)
+        );
+        if let Some(banned) =
+            config.ban_for_requirement(dependency.crate_name(), &dependency.version_req)
+        {
+            violations.push(Violation {
+                label: label.clone(),
+                problem: Problem::Banned(banned.clone()),
+                path: vec![],
+            });
+        }
+        let (source, allowed) = match (dependency.path(), dependency.git(), dependency.registry()) {
+            (Some(_), _, _) => continue,
+            (None, Some(git), _) => (git.to_owned(), source_allowed(git, &registries)),
+            (None, None, Some(registry)) => match registry_index(registry)? {
+                Some(index) => {
+                    let allowed = source_allowed(registry, &registries)
+                        || source_allowed(source_url(&index), &registries);
+                    (index, allowed)
+                }
+                None => (registry.to_owned(), source_allowed(registry, &registries)),
+            },
+            (None, None, None) => (CRATES_IO.to_owned(), source_allowed(CRATES_IO, &registries)),
+        };
+        if !allowed {
+            violations.push(Violation {
+                label,
+                problem: Problem::Source(source),
+                path: vec![],
+            });
+        }
+    }
+    Ok(violations)
+}
+
+/// Checks every locked package that isn't part of the workspace against the config's banned
+/// crates and allowed registries.
+pub fn check_lockfile(config: &Config, lockfile: &Lockfile) -> Result<Vec<Violation>> {
+    let registries = allowed_registries(config)?;
+    let mut violations = vec![];
+    for (position, package) in lockfile.packages.iter().enumerate() {
+        let source = match &package.source {
+            Some(source) => source,
+            None => continue,
+        };
+        let mut problems = vec![];
+        if let Some(banned) = config.ban_for(&package.name, Some(&package.version)) {
+            problems.push(Problem::Banned(banned.clone()));
+        }
+        if !source_allowed(source_url(source), &registries) {
+            problems.push(Problem::Source(source.to_owned()));
+        }
+        for problem in problems {
+            violations.push(Violation {
+                label: lockfile.label(position),
+                problem,
+                path: lockfile
+                    .path_to(position)
+                    .into_iter()
+                    .map(|step| lockfile.label(step))
+                    .collect(),
+            });
+        }
+    }
+    Ok(violations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cargoreader::parse_cargo_file;
+    use crate::lockfile::parse_lockfile;
+
+    fn config(banned: &[(&str, Option<&str>)], registries: &[&str]) -> Config {
+        Config {
+            banned: banned
+                .iter()
+                .map(|(name, version_req)| BannedCrate {
+                    name: name.to_string(),
+                    version_req: version_req.map(|version_req| version_req.parse().unwrap()),
+                })
+                .collect(),
+            registries: registries.iter().map(|url| url.to_string()).collect(),
+            ..Config::default()
+        }
+    }
+
+    fn problems(violations: &[Violation]) -> Vec<(String, String)> {
+        violations
+            .iter()
+            .map(|violation| {
+                let problem = match &violation.problem {
+                    Problem::Banned(banned) => format!("banned {}", banned),
+                    Problem::Source(source) => format!("source {}", source),
+                };
+                (violation.label.clone(), problem)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn source_urls_drop_the_kind_and_reference() {
+        assert_eq!(
+            source_url("registry+https://github.com/rust-lang/crates.io-index"),
+            INDEX_URL
+        );
+        assert_eq!(
+            source_url("git+https://github.com/a/b?branch=main#abc"),
+            "https://github.com/a/b"
+        );
+        assert_eq!(
+            source_url("sparse+https://index.crates.io/"),
+            CRATES_IO_SPARSE_URL
+        );
+        assert_eq!(
+            source_url("https://example.com/index"),
+            "https://example.com/index"
+        );
+    }
+
+    #[test]
+    fn crates_io_is_allowed_by_name_or_url() {
+        let registries = [
+            "crates-io".to_owned(),
+            "https://example.com/index/".to_owned(),
+        ];
+        assert!(source_allowed(CRATES_IO, &registries));
+        assert!(source_allowed(INDEX_URL, &registries));
+        assert!(source_allowed("https://index.crates.io", &registries));
+        assert!(source_allowed("https://example.com/index", &registries));
+        assert!(!source_allowed("https://github.com/a/b", &registries));
+        assert!(source_allowed("https://github.com/a/b", &[]));
+        assert!(source_allowed(CRATES_IO, &[INDEX_URL.to_owned()]));
+        assert!(!source_allowed(
+            INDEX_URL,
+            &["https://example.com/index".to_owned()]
+        ));
+    }
+
+    #[test]
+    fn checks_manifest_requirements() {
+        env::set_var(
+            "CARGO_REGISTRIES_CARP_POLICY_TEST_INDEX",
+            "sparse+https://registry.example.com/index/",
+        );
+        let manifest = r#"
+[dependencies]
+rand = "0.8"
+openssl = "0.10"
+local = { version = "0.1", path = "../local" }
+forked = { version = "0.1", git = "https://github.com/a/forked" }
+internal = { version = "1", registry = "carp-policy-test" }
+"#;
+        let dependencies = parse_cargo_file(manifest.parse().unwrap()).unwrap();
+        let config = config(
+            &[("openssl", None), ("local", None)],
+            &[
+                "https://github.com/rust-lang/crates.io-index",
+                "https://registry.example.com/index",
+            ],
+        );
+        let mut found = problems(&check_manifest(&config, &dependencies).unwrap());
+        found.sort();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    "forked (0.1)".to_owned(),
+                    "source https://github.com/a/forked".to_owned()
+                ),
+                ("local (0.1)".to_owned(), "banned local".to_owned()),
+                ("openssl (0.10)".to_owned(), "banned openssl".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_locked_packages_with_their_path() {
+        let lockfile = parse_lockfile(
+            r#"
+[[package]]
+name = "demo"
+version = "0.1.0"
+dependencies = ["rand", "forked"]
+
+[[package]]
+name = "rand"
+version = "0.7.3"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "forked"
+version = "0.1.0"
+source = "git+https://github.com/a/forked?branch=main#abc"
+"#
+            .parse()
+            .unwrap(),
+        )
+        .unwrap();
+        let config = config(&[("rand", Some("<0.8")), ("demo", None)], &["crates-io"]);
+        let violations = check_lockfile(&config, &lockfile).unwrap();
+        assert_eq!(
+            problems(&violations),
+            vec![
+                ("rand v0.7.3".to_owned(), "banned rand@<0.8".to_owned()),
+                (
+                    "forked v0.1.0".to_owned(),
+                    "source git+https://github.com/a/forked?branch=main#abc".to_owned()
+                ),
+            ]
+        );
+        assert_eq!(violations[0].path, vec!["demo v0.1.0", "rand v0.7.3"]);
+    }
+}
@@ -0,0 +1,579 @@
+use crate::cargoreader::PackageFeatures;
+use crate::cratesio::{load_crates, IndexedCrate};
+use crate::dependency::{transform_dependency_version, Dependency};
+use crate::utils::Result;
+use crates_index::{DependencyKind, Index};
+use semver::{Version, VersionReq};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+
+/// A requirement on a crate, as written in a manifest or an index entry.
+#[derive(Debug, Clone)]
+pub struct Requirement {
+    pub crate_name: String,
+    pub version_req: VersionReq,
+    pub features: Vec<String>,
+    pub default_features: bool,
+}
+
+/// A version of a crate picked by the resolver, along with the features requested of it.
+#[derive(Debug, Clone)]
+pub struct ResolvedCrate {
+    pub name: String,
+    pub version: Version,
+    pub features: BTreeSet<String>,
+    /// Positions in `DependencyGraph::crates` of the crates this one depends on.
+    pub dependencies: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DependencyGraph {
+    pub crates: Vec<ResolvedCrate>,
+    /// Positions in `crates` of the requirements the graph was resolved from.
+    pub roots: Vec<usize>,
+}
+
+/// What a set of enabled features turns on in a package.
+#[derive(Default)]
+struct Activation {
+    optional_dependencies: HashSet<String>,
+    dependency_features: HashMap<String, BTreeSet<String>>,
+}
+
+/// Follows enabled features through a package's feature table, collecting the optional
+/// dependencies they turn on and the features they enable on dependencies. Handles implicit
+/// features for optional dependencies, `dep:` and weak `dep?/feature` entries.
+fn activate_features(
+    feature_table: &HashMap<String, Vec<String>>,
+    optional_dependencies: &HashSet<&str>,
+    enabled: impl IntoIterator<Item = String>,
+) -> Activation {
+    let mut activation = Activation::default();
+    let mut seen = HashSet::new();
+    let mut pending: Vec<String> = enabled.into_iter().collect();
+    let mut weak = vec![];
+
+    while let Some(feature) = pending.pop() {
+        if !seen.insert(feature.clone()) {
+            continue;
+        }
+        if let Some(dependency) = feature.strip_prefix("dep:") {
+            activation
+                .optional_dependencies
+                .insert(dependency.to_owned());
+        } else if let Some((dependency, dependency_feature)) = feature.split_once('/') {
+            if let Some(dependency) = dependency.strip_suffix('?') {
+                weak.push((dependency.to_owned(), dependency_feature.to_owned()));
+            } else {
+                if optional_dependencies.contains(dependency) {
+                    activation
+                        .optional_dependencies
+                        .insert(dependency.to_owned());
+                }
+                activation
+                    .dependency_features
+                    .entry(dependency.to_owned())
+                    .or_default()
+                    .insert(dependency_feature.to_owned());
+            }
+        } else if let Some(enables) = feature_table.get(&feature) {
+            pending.extend(enables.iter().cloned());
+        } else if optional_dependencies.contains(feature.as_str()) {
+            activation.optional_dependencies.insert(feature);
+        }
+    }
+
+    for (dependency, dependency_feature) in weak {
+        if !optional_dependencies.contains(dependency.as_str())
+            || activation.optional_dependencies.contains(&dependency)
+        {
+            activation
+                .dependency_features
+                .entry(dependency)
+                .or_default()
+                .insert(dependency_feature);
+        }
+    }
+    activation
+}
+
+/// The requirements a manifest's dependencies put on the registry, with the package's default
+/// features deciding which optional dependencies are on.
+pub fn manifest_requirements(
+    dependencies: &[Dependency],
+    package_features: &PackageFeatures,
+) -> Result<Vec<Requirement>> {
+    let optional: HashSet<&str> = dependencies
+        .iter()
+        .filter(|dependency| dependency.is_optional())
+        .map(|dependency| dependency.name.as_str())
+        .collect();
+    let activation = activate_features(package_features, &optional, ["default".to_owned()]);
+
+    Ok(dependencies
+        .iter()
+        .filter(|dependency| {
+            !dependency.is_optional() || activation.optional_dependencies.contains(&dependency.name)
+        })
+        .map(|dependency| {
+            let mut features = dependency.features();
+            if let Some(enabled) = activation.dependency_features.get(&dependency.name) {
+                features.extend(enabled.iter().cloned());
+            }
+            Requirement {
+                crate_name: dependency.crate_name().to_owned(),
+                version_req: dependency.version_req.clone(),
+                features,
+                default_features: dependency.default_features(),
+            }
+        })
+        .collect())
+}
+
+/// Parses a `crate[@requirement]` argument, defaulting to any stable version.
+pub fn parse_candidate(arg: &str) -> Result<Requirement> {
+    let (crate_name, version_req) = arg.split_once('@').unwrap_or((arg, "*"));
+    Ok(Requirement {
+        crate_name: crate_name.to_owned(),
+        version_req: version_req.parse()?,
+        features: vec![],
+        default_features: true,
+    })
+}
+
+struct Resolver<'a> {
+    index: &'a Index,
+    /// Loaded crates by their name in the index.
+    loaded: HashMap<String, IndexedCrate>,
+    /// Index names by the names they were asked for with, which can differ in case and `-`/`_`.
+    names: HashMap<String, String>,
+    graph: DependencyGraph,
+    positions: HashMap<(String, Version), usize>,
+    queue: VecDeque<usize>,
+}
+
+impl Resolver<'_> {
+    fn load<'n>(&mut self, names: impl IntoIterator<Item = &'n str>) -> Result<()> {
+        let mut missing: Vec<&str> = names
+            .into_iter()
+            .filter(|name| !self.names.contains_key(*name))
+            .collect();
+        missing.sort_unstable();
+        missing.dedup();
+
+        for (name, crte) in missing.iter().zip(load_crates(self.index, &missing)?) {
+            self.names.insert(name.to_string(), crte.name().to_owned());
+            self.loaded.insert(crte.name().to_owned(), crte);
+        }
+        Ok(())
+    }
+
+    /// Picks a version for a requirement and adds it to the graph, queueing it to have its own
+    /// dependencies resolved if it's new or the requirement enables features it didn't have.
+    fn request(&mut self, requirement: &Requirement, required_by: &str) -> Result<usize> {
+        let crte = &self.loaded[&self.names[&requirement.crate_name]];
+        let selected = crte
+            .select_version(&requirement.version_req)
+            .ok_or(format!(
+                "No version of '{}' matches '{}', required by {}.",
+                requirement.crate_name, requirement.version_req, required_by
+            ))?;
+        let key = (crte.name().to_owned(), crte.versions[selected].clone());
+
+        let position = match self.positions.get(&key) {
+            Some(position) => *position,
+            None => {
+                self.graph.crates.push(ResolvedCrate {
+                    name: key.0.clone(),
+                    version: key.1.clone(),
+                    features: BTreeSet::new(),
+                    dependencies: vec![],
+                });
+                let position = self.graph.crates.len() - 1;
+                self.positions.insert(key, position);
+                self.queue.push_back(position);
+                position
+            }
+        };
+
+        let features = &mut self.graph.crates[position].features;
+        let before = features.len();
+        features.extend(requirement.features.iter().cloned());
+        if requirement.default_features {
+            features.insert("default".to_owned());
+        }
+        if features.len() > before && !self.queue.contains(&position) {
+            self.queue.push_back(position);
+        }
+        Ok(position)
+    }
+
+    fn expand(&mut self, position: usize) -> Result<()> {
+        let resolved = &self.graph.crates[position];
+        let crte = &self.loaded[&resolved.name];
+        let selected = crte
+            .versions
+            .iter()
+            .position(|version| version == &resolved.version)
+            .expect("Resolved version is missing from its crate.");
+        let crate_version = &crte.crte.versions()[selected];
+
+        let optional: HashSet<&str> = crate_version
+            .dependencies()
+            .iter()
+            .filter(|dependency| dependency.is_optional())
+            .map(|dependency| dependency.name())
+            .collect();
+        let activation = activate_features(
+            crate_version.features(),
+            &optional,
+            resolved.features.iter().cloned(),
+        );
+
+        let mut requirements = vec![];
+        for dependency in crate_version.dependencies() {
+            if dependency.kind() == DependencyKind::Dev
+                || (dependency.is_optional()
+                    && !activation.optional_dependencies.contains(dependency.name()))
+            {
+                continue;
+            }
+            let mut features = dependency.features().to_vec();
+            if let Some(enabled) = activation.dependency_features.get(dependency.name()) {
+                features.extend(enabled.iter().cloned());
+            }
+            requirements.push(Requirement {
+                crate_name: dependency.crate_name().to_owned(),
+                version_req: dependency.requirement().parse()?,
+                features,
+                default_features: dependency.has_default_features(),
+            });
+        }
+        let required_by = format!("'{} v{}'", resolved.name, resolved.version);
+
+        self.load(
+            requirements
+                .iter()
+                .map(|requirement| requirement.crate_name.as_str()),
+        )?;
+        let mut dependencies = vec![];
+        for requirement in &requirements {
+            dependencies.push(self.request(requirement, &required_by)?);
+        }
+        dependencies.sort_unstable_by(|a, b| {
+            let (a, b) = (&self.graph.crates[*a], &self.graph.crates[*b]);
+            (&a.name, &a.version).cmp(&(&b.name, &b.version))
+        });
+        dependencies.dedup();
+        self.graph.crates[position].dependencies = dependencies;
+        Ok(())
+    }
+}
+
+/// Resolves requirements and everything they pull in transitively against the index, picking the
+/// highest non-yanked version for each requirement. Target-specific dependencies are always
+/// included, since there's no target to evaluate their `cfg` against.
+pub fn resolve(index: &Index, requirements: &[Requirement]) -> Result<DependencyGraph> {
+    let mut resolver = Resolver {
+        index,
+        loaded: HashMap::new(),
+        names: HashMap::new(),
+        graph: DependencyGraph::default(),
+        positions: HashMap::new(),
+        queue: VecDeque::new(),
+    };
+    resolver.load(
+        requirements
+            .iter()
+            .map(|requirement| requirement.crate_name.as_str()),
+    )?;
+    for requirement in requirements {
+        let root = resolver.request(requirement, "the manifest")?;
+        resolver.graph.roots.push(root);
+    }
+    while let Some(position) = resolver.queue.pop_front() {
+        resolver.expand(position)?;
+    }
+    Ok(resolver.graph)
+}
+
+/// How a dependency graph changes between two resolutions, by crate name.
+#[derive(Debug, Default)]
+pub struct Impact {
+    pub added: Vec<(String, Vec<Version>)>,
+    pub removed: Vec<(String, Vec<Version>)>,
+    /// Crates in both graphs with different versions, before and after.
+    pub changed: Vec<(String, Vec<Version>, Vec<Version>)>,
+}
+
+impl Impact {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+pub fn compare_graphs(before: &DependencyGraph, after: &DependencyGraph) -> Impact {
+    let before_versions = before.versions_by_name();
+    let after_versions = after.versions_by_name();
+    let mut impact = Impact::default();
+
+    for (name, versions) in &before_versions {
+        match after_versions.get(name) {
+            None => impact.removed.push((name.to_owned(), versions.clone())),
+            Some(after) if after != versions => {
+                impact
+                    .changed
+                    .push((name.to_owned(), versions.clone(), after.clone()))
+            }
+            Some(_) => (),
+        }
+    }
+    for (name, versions) in &after_versions {
+        if !before_versions.contains_key(name) {
+            impact.added.push((name.to_owned(), versions.clone()));
+        }
+    }
+    impact
+}
+
+/// How the resolved graph changes when the dependency `name` is moved to `ver_str`, or added at
+/// `ver_str` if the manifest doesn't have it yet.
+pub fn requirement_impact(
+    index: &Index,
+    dependencies: &[Dependency],
+    package_features: &PackageFeatures,
+    before: &DependencyGraph,
+    name: &str,
+    ver_str: &str,
+) -> Result<Impact> {
+    let requirements = if dependencies
+        .iter()
+        .any(|dependency| dependency.name == name)
+    {
+        let changed = dependencies
+            .iter()
+            .map(|dependency| {
+                if dependency.name == name {
+                    transform_dependency_version(ver_str, dependency.to_owned())
+                } else {
+                    Ok(dependency.to_owned())
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+        manifest_requirements(&changed, package_features)?
+    } else {
+        let mut requirements = manifest_requirements(dependencies, package_features)?;
+        requirements.push(parse_candidate(&format!("{}@{}", name, ver_str))?);
+        requirements
+    };
+    let after = resolve(index, &requirements)?;
+    Ok(compare_graphs(before, &after))
+}
+
+impl DependencyGraph {
+    /// Every resolved version of each crate, lowest first.
+    pub fn versions_by_name(&self) -> BTreeMap<String, Vec<Version>> {
+        let mut versions: BTreeMap<String, Vec<Version>> = BTreeMap::new();
+        for resolved in &self.crates {
+            versions
+                .entry(resolved.name.to_owned())
+                .or_default()
+                .push(resolved.version.clone());
+        }
+        for crate_versions in versions.values_mut() {
+            crate_versions.sort();
+        }
+        versions
+    }
+
+    pub fn label(&self, position: usize) -> String {
+        let resolved = &self.crates[position];
+        format!("{} v{}", resolved.name, resolved.version)
+    }
+
+    /// For every crate, the positions of the crates that depend on it.
+    pub fn dependents(&self) -> Vec<Vec<usize>> {
+        let mut dependents = vec![vec![]; self.crates.len()];
+        for (position, resolved) in self.crates.iter().enumerate() {
+            for dependency in &resolved.dependencies {
+                dependents[*dependency].push(position);
+            }
+        }
+        dependents
+    }
+
+    pub fn find(&self, name: &str) -> Vec<usize> {
+        (0..self.crates.len())
+            .filter(|position| self.crates[*position].name == name)
+            .collect()
+    }
+
+    /// Groups of crates that were resolved to more than one version, sorted by name.
+    pub fn duplicates(&self) -> Vec<Vec<usize>> {
+        let mut by_name: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (position, resolved) in self.crates.iter().enumerate() {
+            by_name.entry(&resolved.name).or_default().push(position);
+        }
+        let mut duplicates: Vec<_> = by_name
+            .into_values()
+            .filter(|positions| positions.len() > 1)
+            .collect();
+        for positions in &mut duplicates {
+            positions.sort_by(|a, b| self.crates[*a].version.cmp(&self.crates[*b].version));
+        }
+        duplicates.sort_by(|a, b| self.crates[a[0]].name.cmp(&self.crates[b[0]].name));
+        duplicates
+    }
+
+    /// Every crate reachable from `starts`, including them.
+    pub fn reachable(&self, starts: &[usize]) -> BTreeSet<usize> {
+        let mut reachable = BTreeSet::new();
+        let mut pending = starts.to_vec();
+        while let Some(position) = pending.pop() {
+            if reachable.insert(position) {
+                pending.extend(self.crates[position].dependencies.iter().copied());
+            }
+        }
+        reachable
+    }
+
+    /// Draws the graph as a tree from each start, following `edges` (the crates' dependencies, or
+    /// their dependents for an inverted tree). Crates that were already drawn are marked `(*)`
+    /// instead of being drawn again.
+    pub fn tree_lines(
+        &self,
+        starts: &[usize],
+        edges: &[Vec<usize>],
+        max_depth: Option<usize>,
+    ) -> Vec<String> {
+        let mut lines = vec![];
+        let mut drawn = HashSet::new();
+        for start in starts {
+            self.push_tree_lines(*start, edges, max_depth, 0, "", "", &mut drawn, &mut lines);
+        }
+        lines
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn push_tree_lines(
+        &self,
+        position: usize,
+        edges: &[Vec<usize>],
+        max_depth: Option<usize>,
+        depth: usize,
+        branch: &str,
+        prefix: &str,
+        drawn: &mut HashSet<usize>,
+        lines: &mut Vec<String>,
+    ) {
+        let expand = !edges[position].is_empty() && max_depth.is_none_or(|max| depth < max);
+        if expand && !drawn.insert(position) {
+            lines.push(format!("{}{}{} (*)", prefix, branch, self.label(position)));
+            return;
+        }
+        lines.push(format!("{}{}{}", prefix, branch, self.label(position)));
+        if !expand {
+            return;
+        }
+
+        let child_prefix = match branch {
+            "├── " => format!("{}│   ", prefix),
+            "└── " => format!("{}    ", prefix),
+            _ => prefix.to_owned(),
+        };
+        for (i, child) in edges[position].iter().enumerate() {
+            let child_branch = if i + 1 == edges[position].len() {
+                "└── "
+            } else {
+                "├── "
+            };
+            self.push_tree_lines(
+                *child,
+                edges,
+                max_depth,
+                depth + 1,
+                child_branch,
+                &child_prefix,
+                drawn,
+                lines,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Activates `enabled` in a package with the given feature table and optional dependencies,
+    /// returning the optional dependencies turned on and the features enabled on dependencies.
+    fn activate(
+        features: &[(&str, &[&str])],
+        optional: &[&str],
+        enabled: &[&str],
+    ) -> (BTreeSet<String>, BTreeMap<String, BTreeSet<String>>) {
+        let feature_table = features
+            .iter()
+            .map(|(name, enables)| {
+                let enables = enables.iter().map(|enabled| enabled.to_string()).collect();
+                (name.to_string(), enables)
+            })
+            .collect();
+        let activation = activate_features(
+            &feature_table,
+            &optional.iter().copied().collect(),
+            enabled.iter().map(|feature| feature.to_string()),
+        );
+        (
+            activation.optional_dependencies.into_iter().collect(),
+            activation.dependency_features.into_iter().collect(),
+        )
+    }
+
+    fn names(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn follows_features_to_dependency_features() {
+        let (optional, features) = activate(
+            &[("default", &["std"]), ("std", &["serde/std"])],
+            &["serde"],
+            &["default"],
+        );
+        assert_eq!(optional, names(&["serde"]));
+        assert_eq!(features["serde"], names(&["std"]));
+    }
+
+    #[test]
+    fn optional_dependencies_are_features_of_their_own() {
+        let (optional, features) = activate(&[], &["serde", "rayon"], &["serde"]);
+        assert_eq!(optional, names(&["serde"]));
+        assert!(features.is_empty());
+    }
+
+    #[test]
+    fn dep_entries_only_turn_on_the_dependency() {
+        let (optional, features) =
+            activate(&[("json", &["dep:serde_json"])], &["serde_json"], &["json"]);
+        assert_eq!(optional, names(&["serde_json"]));
+        assert!(features.is_empty());
+    }
+
+    #[test]
+    fn weak_features_wait_for_their_dependency() {
+        let table: &[(&str, &[&str])] = &[("std", &["serde?/std"])];
+        let (optional, features) = activate(table, &["serde"], &["std"]);
+        assert!(optional.is_empty());
+        assert!(features.is_empty());
+
+        let (optional, features) = activate(table, &["serde"], &["std", "serde"]);
+        assert_eq!(optional, names(&["serde"]));
+        assert_eq!(features["serde"], names(&["std"]));
+    }
+
+    #[test]
+    fn feature_cycles_end() {
+        let (optional, _) = activate(&[("a", &["b"]), ("b", &["a", "dep:log"])], &["log"], &["a"]);
+        assert_eq!(optional, names(&["log"]));
+    }
+}
@@ -0,0 +1,81 @@
+use crate::cratesio::IndexedCrate;
+use crates_index::{Crate, Index, INDEX_GIT_URL};
+use git2::{Repository, Signature};
+use std::path::{Path, PathBuf};
+use std::{env, fs, process};
+
+/// A crate as the index would have it, from `(version, yanked, features, optional deps)`.
+pub fn indexed_crate(versions: &[(&str, bool, &str, &[&str])]) -> IndexedCrate {
+    let lines: Vec<String> = versions
+        .iter()
+        .map(|(version, yanked, features, optional)| {
+            let deps = optional
+                .iter()
+                .map(|name| {
+                    format!(
+                        r#"{{"name":"{}","req":"^1","features":[],"optional":true,"default_features":true,"target":null,"kind":"normal"}}"#,
+                        name
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(",");
+            format!(
+                r#"{{"name":"demo","vers":"{}","deps":[{}],"features":{},"cksum":"{}","yanked":{}}}"#,
+                version,
+                deps,
+                features,
+                "0".repeat(64),
+                yanked
+            )
+        })
+        .collect();
+    IndexedCrate::new(Crate::from_slice(lines.join("\n").as_bytes()).unwrap()).unwrap()
+}
+
+/// An empty directory of its own for a test to write files in.
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("carp-test-{}-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Where the index keeps a crate: `1/a`, `2/ab`, `3/a/abc` or `ab/cd/abcd...`.
+fn index_path(name: &str) -> PathBuf {
+    match name.len() {
+        1 => Path::new("1").join(name),
+        2 => Path::new("2").join(name),
+        3 => Path::new("3").join(&name[..1]).join(name),
+        _ => Path::new(&name[..2]).join(&name[2..4]).join(name),
+    }
+}
+
+/// A git index in `dir` with each crate published at `0.1.0`, `0.1.1` and so on, `count` times.
+pub fn git_index(dir: &Path, crates: &[(&str, usize)]) -> Index {
+    let repo = Repository::init(dir).unwrap();
+    fs::write(dir.join("config.json"), "{}").unwrap();
+    for (name, count) in crates {
+        let lines: Vec<_> = (0..*count)
+            .map(|patch| {
+                format!(
+                    r#"{{"name":"{}","vers":"0.1.{}","deps":[],"features":{{}},"cksum":"{}","yanked":false}}"#,
+                    name,
+                    patch,
+                    "0".repeat(64)
+                )
+            })
+            .collect();
+        let path = dir.join(index_path(&name.to_lowercase()));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, lines.join("\n")).unwrap();
+    }
+    let mut files = repo.index().unwrap();
+    files
+        .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+        .unwrap();
+    let tree = repo.find_tree(files.write_tree().unwrap()).unwrap();
+    let signature = Signature::now("carp", "carp@example.com").unwrap();
+    repo.commit(Some("HEAD"), &signature, &signature, "index", &tree, &[])
+        .unwrap();
+    Index::with_path(dir, INDEX_GIT_URL).unwrap()
+}
@@ -0,0 +1,241 @@
+use crate::dependency::Dependency;
+use crate::utils::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+fn push_rust_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            push_rust_files(&entry?.path(), files)?;
+        }
+    } else if path.is_file() && path.extension().is_some_and(|extension| extension == "rs") {
+        files.push(path.to_owned());
+    }
+    Ok(())
+}
+
+/// Every Rust source file of the package: the conventional `src`, `tests`, `examples` and
+/// `benches` directories, `build.rs`, and any target or build script the manifest points
+/// elsewhere.
+pub fn source_files(package_dir: &Path, cargo_file: &Value) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for dir in ["src", "tests", "examples", "benches"] {
+        push_rust_files(&package_dir.join(dir), &mut files)?;
+    }
+    push_rust_files(&package_dir.join("build.rs"), &mut files)?;
+
+    let mut paths = vec![];
+    if let Some(build) = cargo_file
+        .get("package")
+        .and_then(|package| package.get("build"))
+        .and_then(Value::as_str)
+    {
+        paths.push(build);
+    }
+    if let Some(path) = cargo_file
+        .get("lib")
+        .and_then(|lib| lib.get("path"))
+        .and_then(Value::as_str)
+    {
+        paths.push(path);
+    }
+    for section in ["bin", "test", "example", "bench"] {
+        for target in cargo_file
+            .get(section)
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            if let Some(path) = target.get("path").and_then(Value::as_str) {
+                paths.push(path);
+            }
+        }
+    }
+    for path in paths {
+        push_rust_files(&package_dir.join(path), &mut files)?;
+    }
+
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// How many `#`s the raw string literal starting at `i` (`r"`, `r#"`, `br##"`...) has, if one
+/// starts there.
+fn raw_string_hashes(chars: &[char], i: usize) -> Option<usize> {
+    let prefix_start = if i > 0 && chars[i - 1] == 'b' {
+        i - 1
+    } else {
+        i
+    };
+    if chars[i] != 'r' || (prefix_start > 0 && is_ident_char(chars[prefix_start - 1])) {
+        return None;
+    }
+    let hashes = chars[i + 1..].iter().take_while(|c| **c == '#').count();
+    (chars.get(i + 1 + hashes) == Some(&'"')).then_some(hashes)
+}
+
+/// Blanks out comments and the contents of string literals, raw ones included, so that crate
+/// names mentioned in them don't count as uses.
+fn strip_comments_and_strings(source: &str) -> String {
+    let chars: Vec<char> = source.chars().collect();
+    let mut stripped = String::with_capacity(source.len());
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if chars[i] == '/' && chars.get(i + 1) == Some(&'*') {
+            let mut depth = 0;
+            while i < chars.len() {
+                if chars[i] == '/' && chars.get(i + 1) == Some(&'*') {
+                    depth += 1;
+                    i += 2;
+                } else if chars[i] == '*' && chars.get(i + 1) == Some(&'/') {
+                    depth -= 1;
+                    i += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                } else {
+                    i += 1;
+                }
+            }
+            stripped.push(' ');
+        } else if chars[i] == '\'' && chars.get(i + 2) == Some(&'\'') {
+            // A char literal, which could be a quote that would otherwise start a string.
+            stripped.push_str("' '");
+            i += 3;
+        } else if chars[i] == '\''
+            && chars.get(i + 1) == Some(&'\\')
+            && chars.get(i + 3) == Some(&'\'')
+        {
+            stripped.push_str("' '");
+            i += 4;
+        } else if let Some(hashes) = raw_string_hashes(&chars, i) {
+            let closing: Vec<char> = format!("\"{}", "#".repeat(hashes)).chars().collect();
+            i += hashes + 2;
+            while i < chars.len() && !chars[i..].starts_with(&closing) {
+                i += 1;
+            }
+            i += closing.len();
+            stripped.push_str("\"\"");
+        } else if chars[i] == '"' {
+            i += 1;
+            while i < chars.len() && chars[i] != '"' {
+                i += if chars[i] == '\\' { 2 } else { 1 };
+            }
+            i += 1;
+            stripped.push_str("\"\"");
+        } else {
+            stripped.push(chars[i]);
+            i += 1;
+        }
+    }
+    stripped
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Whether code ends with a keyword, rather than an identifier that ends with the same letters.
+fn ends_with_keyword(code: &str, keyword: &str) -> bool {
+    code.strip_suffix(keyword)
+        .is_some_and(|before| !before.ends_with(is_ident_char))
+}
+
+/// Whether source code refers to a crate by its identifier: as the start of a path
+/// (`ident::...`), or in `use ident`, `use ::ident` or `extern crate ident`.
+pub fn references_crate(source: &str, ident: &str) -> bool {
+    let source = strip_comments_and_strings(source);
+    let mut search_from = 0;
+    while let Some(found) = source[search_from..].find(ident) {
+        let start = search_from + found;
+        let end = start + ident.len();
+        search_from = end;
+
+        let before = &source[..start];
+        let after = &source[end..];
+        if before.ends_with(is_ident_char) || after.starts_with(is_ident_char) {
+            continue;
+        }
+        let before = before.trim_end();
+        let before = before.strip_suffix("::").unwrap_or(before).trim_end();
+        if after.trim_start().starts_with("::")
+            || ends_with_keyword(before, "use")
+            || ends_with_keyword(before, "extern crate")
+        {
+            return true;
+        }
+    }
+    false
+}
+
+/// The dependencies none of the sources refer to. Renamed dependencies are looked for by the name
+/// they're renamed to, and `-` becomes `_` as it does in code.
+pub fn unused_dependencies(dependencies: &[Dependency], sources: &[String]) -> Vec<Dependency> {
+    dependencies
+        .iter()
+        .filter(|dependency| {
+            let ident = dependency.name.replace('-', "_");
+            !sources
+                .iter()
+                .any(|source| references_crate(source, &ident))
+        })
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_comments_and_strings() {
+        let source = "let a = \"serde::x\"; // serde::y\n/* serde /* nested */ serde */ b";
+        let stripped = strip_comments_and_strings(source);
+        assert!(!stripped.contains("serde"));
+        assert!(stripped.ends_with(" b"));
+    }
+
+    #[test]
+    fn strips_raw_strings() {
+        let source = "let a = r#\"a \" serde::x\"#; let b = br\"\\\"; serde::y();";
+        let stripped = strip_comments_and_strings(source);
+        assert_eq!(stripped, "let a = \"\"; let b = b\"\"; serde::y();");
+    }
+
+    #[test]
+    fn keeps_raw_identifiers() {
+        let source = "let r#type = 1; serde::x();";
+        assert_eq!(strip_comments_and_strings(source), source);
+    }
+
+    #[test]
+    fn char_literals_do_not_start_strings() {
+        let source = "let q = '\"'; serde::x();";
+        assert!(strip_comments_and_strings(source).contains("serde::x"));
+    }
+
+    #[test]
+    fn finds_references() {
+        assert!(references_crate("fn f() { serde::x(); }", "serde"));
+        assert!(references_crate("use serde;", "serde"));
+        assert!(references_crate("use ::serde::Serialize;", "serde"));
+        assert!(references_crate("extern crate serde;", "serde"));
+        assert!(references_crate("pub use serde as s;", "serde"));
+    }
+
+    #[test]
+    fn ignores_look_alikes() {
+        assert!(!references_crate("let serde_json = 1;", "serde"));
+        assert!(!references_crate("my_serde::x();", "serde"));
+        assert!(!references_crate("let x = serde;", "serde"));
+        assert!(!references_crate("m! { refuse serde }", "serde"));
+        assert!(!references_crate("// serde::x()", "serde"));
+        assert!(!references_crate("let s = r\"serde::x\";", "serde"));
+    }
+}
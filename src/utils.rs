@@ -1,5 +1,59 @@
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
+/// The edit distance between two strings, counted in chars, where swapping two neighbouring
+/// chars counts as a single edit (optimal string alignment distance).
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut before_previous: Vec<usize> = vec![0; b.len() + 1];
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for i in 1..=a.len() {
+        let mut current = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            current[j] = (previous[j - 1] + cost)
+                .min(previous[j] + 1)
+                .min(current[j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before_previous[j - 2] + 1);
+            }
+        }
+        before_previous = previous;
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Replaces a file's contents by writing them to a temporary file next to it and renaming that
+/// over it, so the file is never left half written. The file keeps its permissions.
+pub fn write_file_atomically(path: &std::path::Path, contents: &str) -> Result<()> {
+    use std::io::Write;
+
+    let file_name = path
+        .file_name()
+        .ok_or(format!("Could not write to '{}'.", path.display()))?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(".carp-tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    let written = std::fs::File::create(&temp_path).and_then(|mut file| {
+        if let Ok(metadata) = std::fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()
+    });
+    match written.and_then(|()| std::fs::rename(&temp_path, path)) {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            let _ = std::fs::remove_file(&temp_path);
+            Err(err.into())
+        }
+    }
+}
+
 pub trait Join<T>
 where
     Self: Iterator<Item = T>,
@@ -42,29 +96,91 @@ where
     I: Iterator<Item = T>,
     F: Fn(&T) -> bool,
 {
-    fn one(self, predicate: F) -> Option<T> {
-        for el in self {
-            if predicate(&el) {
-                return Some(el);
-            }
-        }
-        None
+    fn one(mut self, predicate: F) -> Option<T> {
+        self.find(predicate)
     }
 }
 
 pub trait Pass<T, F>
 where
-    F: Fn(&T) -> (),
+    F: Fn(&T),
 {
     fn pass(self, predicate: F) -> T;
 }
 
 impl<T, F> Pass<T, F> for T
 where
-    F: Fn(&T) -> (),
+    F: Fn(&T),
 {
     fn pass(self, predicate: F) -> T {
         predicate(&self);
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+    use std::fs;
+
+    #[test]
+    fn edit_distance_counts_single_edits() {
+        assert_eq!(edit_distance("serde", "serde"), 0);
+        assert_eq!(edit_distance("serde", "sered"), 1);
+        assert_eq!(edit_distance("serde", "serd"), 1);
+        assert_eq!(edit_distance("serde", "serdes"), 1);
+        assert_eq!(edit_distance("serde", "surde"), 1);
+        assert_eq!(edit_distance("tokio", "toiko"), 1);
+        assert_eq!(edit_distance("tokio", "takyo"), 2);
+    }
+
+    #[test]
+    fn edit_distance_handles_empty_and_multibyte_strings() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("", "rand"), 4);
+        assert_eq!(edit_distance("rand", ""), 4);
+        assert_eq!(edit_distance("naïve", "naive"), 1);
+        assert_eq!(edit_distance("ab", "ba"), 1);
+    }
+
+    #[test]
+    fn atomic_writes_replace_the_file_and_leave_nothing_behind() {
+        let dir = scratch_dir("atomic-replace");
+        let path = dir.join("cargo.toml");
+        write_file_atomically(&path, "[package]\n").unwrap();
+        write_file_atomically(&path, "[dependencies]\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[dependencies]\n");
+        let names: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, vec!["cargo.toml"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn atomic_writes_keep_the_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = scratch_dir("atomic-permissions");
+        let path = dir.join("cargo.toml");
+        fs::write(&path, "").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        write_file_atomically(&path, "[package]\n").unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+    }
+
+    #[test]
+    fn failed_atomic_writes_leave_the_file_alone() {
+        let dir = scratch_dir("atomic-failure");
+        let path = dir.join("cargo.toml");
+        fs::write(&path, "[package]\n").unwrap();
+        // A directory where the temporary file goes makes the write fail.
+        fs::create_dir(dir.join(".cargo.toml.carp-tmp")).unwrap();
+        assert!(write_file_atomically(&path, "[dependencies]\n").is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[package]\n");
+        assert!(dir.join(".cargo.toml.carp-tmp").is_dir());
+    }
+}
@@ -0,0 +1,85 @@
+use crate::common::get_package_dir;
+use crate::utils::Result;
+use std::process::Command;
+
+/// How many lines of a failing command's output are kept.
+const OUTPUT_LINES: usize = 20;
+
+/// The command line `--verify` runs: `check` and `test` are the cargo commands, anything else is
+/// run as written, split into words the way a shell would, so quoted arguments stay whole.
+pub fn verify_command(spec: &str) -> Result<Vec<String>> {
+    match spec {
+        "check" | "test" => Ok(vec!["cargo".to_owned(), spec.to_owned()]),
+        _ => shell_words::split(spec)
+            .map_err(|_| format!("Could not parse the verify command '{}'.", spec).into()),
+    }
+}
+
+/// Runs a command in the package directory, returning whether it succeeded and the end of what it
+/// printed. A command killed by a signal is an error rather than a failure.
+pub fn run_command(command_line: &[String]) -> Result<(bool, String)> {
+    let (program, args) = command_line
+        .split_first()
+        .ok_or("The verify command is empty.")?;
+    let output = Command::new(program)
+        .args(args)
+        .current_dir(get_package_dir())
+        .output()
+        .map_err(|err| format!("Could not run '{}': {}", command_line.join(" "), err))?;
+
+    if output.status.code().is_none() {
+        return Err(format!("'{}' was killed.", command_line.join(" ")).into());
+    }
+
+    let mut printed = String::from_utf8_lossy(&output.stdout).into_owned();
+    printed.push_str(&String::from_utf8_lossy(&output.stderr));
+    let lines: Vec<&str> = printed.lines().collect();
+    let tail = lines[lines.len().saturating_sub(OUTPUT_LINES)..].join("\n");
+    Ok((output.status.success(), tail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(spec: &str) -> Vec<String> {
+        verify_command(spec).unwrap()
+    }
+
+    #[test]
+    fn check_and_test_are_cargo_commands() {
+        assert_eq!(words("check"), vec!["cargo", "check"]);
+        assert_eq!(words("test"), vec!["cargo", "test"]);
+    }
+
+    #[test]
+    fn quoted_arguments_stay_whole() {
+        assert_eq!(
+            words("cargo test -- --skip 'slow network'"),
+            vec!["cargo", "test", "--", "--skip", "slow network"]
+        );
+        assert_eq!(
+            words(r#"sh -c "make lint && make test""#),
+            vec!["sh", "-c", "make lint && make test"]
+        );
+        assert!(verify_command("sh -c 'unclosed").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn commands_report_success_and_the_end_of_their_output() {
+        let (passes, output) = run_command(&words("sh -c 'seq 1 30; exit 3'")).unwrap();
+        assert!(!passes);
+        assert_eq!(output.lines().count(), OUTPUT_LINES);
+        assert_eq!(output.lines().last(), Some("30"));
+        assert!(run_command(&words("true")).unwrap().0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn missing_empty_and_killed_commands_are_errors() {
+        assert!(run_command(&[]).is_err());
+        assert!(run_command(&words("carp-no-such-command")).is_err());
+        assert!(run_command(&words("sh -c 'kill -9 $$'")).is_err());
+    }
+}